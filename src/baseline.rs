@@ -3,22 +3,46 @@
 //! Note that due to their simplicty, many of these may violate
 //! budget constraints.
 
+use fastrand::Rng;
 use itertools::Itertools;
 use ndarray::ArrayD;
 
-use crate::{evaluate::evaluate, problem::{Problem, ScheduleType, Solution, TrainLine}};
+use crate::{evaluate::evaluate, problem::{Problem, ScheduleType, Solution, TrainLine, TrainTrackIterator}};
 
 /// Generates a single train that visits every station
 pub fn big_loop(problem: &Problem, ty: ScheduleType) -> Solution {
-    let route = (0..problem.n).collect_vec();
-    let mut built_tracks = ArrayD::<bool>::default(problem.track_costs.shape());
-    for i in 0..problem.n-1 {
-        built_tracks[[i, i+1]] = true; built_tracks[[i+1, i]] = true;
-    }
-    if ty == ScheduleType::Circular {
-        built_tracks[[0, problem.n-1]] = true; built_tracks[[problem.n-1, 0]] = true;
+    single_line(problem, (0..problem.n).collect_vec(), ty)
+}
+
+/// Generates a single train that visits every station, starting from `start`
+/// and always going on to the nearest station it has not visited
+pub fn nearest_neighbour_loop(problem: &Problem, ty: ScheduleType, start: usize) -> Solution {
+    let mut route = vec![start];
+    let mut unvisited = (0..problem.n).filter(|&s| s != start).collect_vec();
+    while let Some(i) = unvisited.iter().position_min_by(|&&a, &&b| {
+        // UNWRAP: the route always has a station
+        let last = *route.last().unwrap();
+        problem.track_times[[last, a]].total_cmp(&problem.track_times[[last, b]])
+    }) {
+        route.push(unvisited.remove(i));
     }
+    single_line(problem, route, ty)
+}
+
+/// Generates a single train that visits every station in a random order
+pub fn random_loop(problem: &Problem, ty: ScheduleType, rng: &mut Rng) -> Solution {
+    let mut route = (0..problem.n).collect_vec();
+    rng.shuffle(&mut route);
+    single_line(problem, route, ty)
+}
+
+/// A solution running one train on a single line, building only the tracks it uses
+fn single_line(problem: &Problem, route: Vec<usize>, ty: ScheduleType) -> Solution {
     let train_lines = vec![TrainLine { route, ty, n: 1 }];
+    let mut built_tracks = ArrayD::<bool>::default(problem.track_costs.shape());
+    for (a, b) in TrainTrackIterator::new(&train_lines[0]) {
+        built_tracks[[a, b]] = true; built_tracks[[b, a]] = true;
+    }
     let obj_value = evaluate(problem, &train_lines);
 
     Solution {
@@ -26,4 +50,4 @@ pub fn big_loop(problem: &Problem, ty: ScheduleType) -> Solution {
         train_lines,
         obj_value
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,708 @@
+//! The command-line interface: parses arguments and dispatches to
+//! the solvers, evaluator and problem generators
+
+use std::{error::Error, fs, sync::{atomic::{AtomicBool, Ordering}, Arc, LazyLock}, thread, time::Duration};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::Deserialize;
+use fastrand::Rng;
+
+use crate::{
+    baseline::big_loop,
+    evaluate::evaluate_detailed,
+    generate::{gen_random_problem, gen_random_problem_location},
+    localsearch::{alns::{default_destroy_operators, default_repair_operators, Alns, AlnsParams}, genetic::GeneticSolver, multistart::{IlsAcceptance, IteratedParams, MultiStart, ScoreSummary}, neighbourhood::{default_operators, WeightedOperator}, metaheuristic::{Calibration, GreatDeluge, GreatDelugeParams, LateAcceptance, LateAcceptanceParams, Reheat, SimAnneal, SimAnnealParams, TabuParams, TabuSearch}, observer::{Observer, SearchEvent}, portfolio::{Island, Portfolio}, trace::Trace, Metaheuristic, Solver, SolveOutcome},
+    parse::{parse_problem, parse_solution, save_problem, save_solution, solution_to_string, Format},
+    problem::{Problem, ScheduleType, Solution, UnservedPolicy, DEFAULT_TRAVEL_TIME}
+};
+
+/// Set when Ctrl-C is pressed, stopping a solve early. `main` sets the handler once for the whole process.
+pub static CANCEL: LazyLock<Arc<AtomicBool>> = LazyLock::new(|| Arc::new(AtomicBool::new(false)));
+
+/// Optimises train lines over a network of stations
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Solve a problem, writing the best solution found
+    Solve(Box<SolveArgs>),
+    /// Score a solution against a problem
+    Evaluate {
+        /// The problem file, in TOML format
+        problem: String,
+        /// The solution file to score, in TOML or JSON format
+        solution: String,
+        /// Write a report of the journey between every pair of stations to this file,
+        /// in JSON if the file ends in `.json`, otherwise TOML
+        #[arg(long, short)]
+        report: Option<String>,
+        #[command(flatten)]
+        evaluation: EvaluationArgs
+    },
+    /// Generate a random problem
+    Generate(GenerateArgs),
+    /// Check that a problem, and optionally a solution to it, is valid
+    Validate {
+        /// The problem file, in TOML format
+        problem: String,
+        /// A solution file to check against the problem, in TOML or JSON format
+        #[arg(long, short)]
+        solution: Option<String>
+    }
+}
+
+/// Which solver to run
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Algorithm {
+    /// A single line visiting every station
+    Baseline,
+    /// Local search with tabu search
+    Tabu,
+    /// Local search with simulated annealing
+    Sa,
+    /// Local search with late acceptance hill climbing
+    Lahc,
+    /// Local search with great deluge threshold accepting
+    Deluge,
+    /// Adaptive large neighbourhood search, destroying and repairing parts of the solution
+    Alns,
+    /// A genetic algorithm, evolving a population of solutions;
+    /// memetic if offspring are improved by tabu search
+    Genetic,
+    /// Several local searches at once, one per thread, sharing their best solutions
+    Portfolio
+}
+
+/// The command-line equivalent of `ScheduleType`
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    Circular, Bidirectional
+}
+impl From<Schedule> for ScheduleType {
+    fn from(schedule: Schedule) -> Self {
+        match schedule {
+            Schedule::Circular => ScheduleType::Circular,
+            Schedule::Bidirectional => ScheduleType::Bidirectional
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct SolveArgs {
+    /// The problem file, in TOML format
+    pub problem: String,
+    /// Where to write the solution, in JSON if the file ends in `.json`, otherwise TOML;
+    /// printed to stdout as TOML if not given
+    #[arg(long, short)]
+    pub output: Option<String>,
+    /// Seed for the random number generator; solving again with the same seed gives the same solution.
+    /// Chosen randomly and printed if not given.
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// The solver to use [default: tabu]
+    #[arg(long, short, value_enum)]
+    pub algorithm: Option<Algorithm>,
+    /// Read the solver and its parameters from this TOML file, with the same names as the options here.
+    /// Options given on the command line take precedence.
+    #[arg(long)]
+    pub config: Option<String>,
+    /// Start the local search from this solution instead, in TOML or JSON format,
+    /// such as a previous run's output or an existing network
+    #[arg(long)]
+    pub initial: Option<String>,
+    /// The schedule of the line built by the baseline solver
+    #[arg(long, value_enum, default_value_t = Schedule::Bidirectional)]
+    pub schedule: Schedule,
+    /// The maximum number of local search iterations
+    #[arg(long, default_value_t = 1000)]
+    pub max_iterations: usize,
+    /// Stop the search after this many seconds
+    #[arg(long)]
+    pub time_limit: Option<f64>,
+    /// Stop the search after this many iterations without finding a better solution
+    #[arg(long)]
+    pub stall_limit: Option<usize>,
+    /// Print the progress of the search to stderr every this many iterations
+    #[arg(long)]
+    pub progress: Option<usize>,
+    /// Write the trajectory of the search to this file,
+    /// in CSV if the file ends in `.csv`, otherwise JSON lines
+    #[arg(long)]
+    pub trace: Option<String>,
+    /// Only write every this many iterations to the trace
+    #[arg(long, default_value_t = 1)]
+    pub trace_every: usize,
+    /// The probability each neighbour is considered
+    #[arg(long, default_value_t = 0.8)]
+    pub neighbour_chance: f64,
+    /// Scale the chance of considering an operator's neighbours, as `operator=weight`;
+    /// a weight of 0 turns the operator off. Every operator has a weight of 1 by default.
+    #[arg(long = "weight", value_name = "OPERATOR=WEIGHT", value_parser = parse_weight)]
+    pub weights: Vec<(String, f64)>,
+    /// The number of threads to split each evaluation across; 0 uses every core
+    #[arg(long, default_value_t = 1)]
+    pub evaluation_threads: usize,
+    /// The number of threads to score neighbours across; 0 uses every core
+    #[arg(long, default_value_t = 1)]
+    pub neighbour_threads: usize,
+    /// Check every incremental evaluation against a full evaluation (slow)
+    #[arg(long)]
+    pub verify_delta: bool,
+    #[command(flatten)]
+    pub evaluation: EvaluationArgs,
+    #[command(flatten)]
+    pub multistart: MultiStartArgs,
+    #[command(flatten)]
+    pub tabu: TabuArgs,
+    #[command(flatten)]
+    pub sa: SimAnnealArgs,
+    #[command(flatten)]
+    pub lahc: LateAcceptanceArgs,
+    #[command(flatten)]
+    pub deluge: GreatDelugeArgs,
+    #[command(flatten)]
+    pub alns: AlnsArgs,
+    #[command(flatten)]
+    pub genetic: GeneticArgs,
+    #[command(flatten)]
+    pub portfolio: PortfolioArgs
+}
+
+/// Overrides for the problem's evaluation model
+#[derive(Args, Debug)]
+#[command(next_help_heading = "Evaluation")]
+pub struct EvaluationArgs {
+    /// The maximum number of times a commuter will change lines
+    #[arg(long)]
+    pub max_transfers: Option<usize>,
+    /// A fixed time added to every change of lines
+    #[arg(long)]
+    pub transfer_penalty: Option<f64>,
+    /// How demand between stations the network does not connect is counted
+    #[arg(long, value_enum)]
+    pub unserved: Option<Unserved>,
+    /// The penalty per unit of unserved demand, or the factor applied to alternative times
+    #[arg(long)]
+    pub unserved_weight: Option<f64>
+}
+
+/// The command-line equivalent of `UnservedPolicy`
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unserved {
+    /// Charge a penalty per unit of unserved demand
+    Penalty,
+    /// Charge the problem's alternative travel time, scaled
+    Alternative,
+    /// Treat any unserved demand as infeasible
+    Hard
+}
+impl EvaluationArgs {
+    /// Overwrite the problem's evaluation model with any values given
+    fn apply(&self, problem: &mut Problem) {
+        if let Some(max_transfers) = self.max_transfers {
+            problem.evaluation.max_transfers = max_transfers;
+        }
+        if let Some(transfer_penalty) = self.transfer_penalty {
+            problem.evaluation.transfer_penalty = transfer_penalty;
+        }
+        let unserved = &mut problem.evaluation.unserved;
+        match self.unserved {
+            Some(Unserved::Penalty) => *unserved = UnservedPolicy::Penalty { weight: DEFAULT_TRAVEL_TIME },
+            Some(Unserved::Alternative) => *unserved = UnservedPolicy::Alternative { factor: 1.0 },
+            Some(Unserved::Hard) => *unserved = UnservedPolicy::Hard,
+            None => {}
+        }
+        if let Some(value) = self.unserved_weight {
+            match unserved {
+                UnservedPolicy::Penalty { weight } => *weight = value,
+                UnservedPolicy::Alternative { factor } => *factor = value,
+                UnservedPolicy::Hard => {}
+            }
+        }
+    }
+}
+
+/// Running a local search several times; not used by the genetic algorithm or portfolio
+#[derive(Args, Debug)]
+#[command(next_help_heading = "Multiple starts")]
+pub struct MultiStartArgs {
+    /// Run the local search this many times, each for up to `--max-iterations`, keeping the best solution
+    #[arg(long, default_value_t = 1)]
+    pub starts: usize,
+    /// Run iterated local search, perturbing the solution carried on from by this many random moves before each start.
+    /// Otherwise, each start is from a different constructed solution.
+    /// Not available with alns, which has no neighbourhood operators to perturb with.
+    #[arg(long)]
+    pub perturbation: Option<usize>,
+    /// With `--perturbation`, which solution to carry on from after each start
+    #[arg(long, value_enum, default_value_t = IlsAccept::Better)]
+    pub ils_accept: IlsAccept,
+    /// With `--ils-accept near-best`, how much worse than the best score a solution can be, as a fraction of it
+    #[arg(long, default_value_t = 0.05)]
+    pub ils_near_best: f64
+}
+
+/// The command-line equivalent of `IlsAcceptance`
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IlsAccept {
+    /// Carry on from a start's solution if it is better than the one it was perturbed from
+    Better,
+    /// Always carry on from the latest start's solution
+    Always,
+    /// Carry on from a start's solution if it is near the best score found
+    NearBest
+}
+
+#[derive(Args, Debug)]
+#[command(next_help_heading = "Tabu search")]
+pub struct TabuArgs {
+    /// The time before tabu times out [default: 1000]
+    #[arg(long)]
+    pub tabu_timeout: Option<usize>,
+    /// The amount to adjust the tabu timeout by every iteration [default: 10]
+    #[arg(long)]
+    pub tabu_size_adjust: Option<usize>
+}
+
+#[derive(Args, Debug)]
+#[command(next_help_heading = "Simulated annealing")]
+pub struct SimAnnealArgs {
+    /// The starting temperature [default: 540]
+    #[arg(long)]
+    pub initial_temp: Option<f64>,
+    /// The factor the temperature is scaled by every iteration;
+    /// by default, chosen so that the final temperature is 1
+    #[arg(long)]
+    pub temp_scale: Option<f64>,
+    /// Pick the temperature from the problem instead, cooling until the iteration or time limit
+    #[arg(long)]
+    pub auto_temp: bool,
+    /// With `--auto-temp`, the probability of accepting a typical worse neighbour at the start [default: 0.5]
+    #[arg(long)]
+    pub initial_acceptance: Option<f64>,
+    /// With `--auto-temp`, the probability of accepting a typical worse neighbour at the end [default: 0.00001]
+    #[arg(long)]
+    pub final_acceptance: Option<f64>,
+    /// Reheat when few of the last this many neighbours considered were accepted
+    #[arg(long)]
+    pub reheat_window: Option<usize>,
+    /// Reheat when fewer than this proportion of the window's neighbours were accepted [default: 0.02]
+    #[arg(long)]
+    pub reheat_below: Option<f64>,
+    /// The factor reheating raises the temperature by [default: 2]
+    #[arg(long)]
+    pub reheat_factor: Option<f64>
+}
+
+#[derive(Args, Debug)]
+#[command(next_help_heading = "Late acceptance hill climbing")]
+pub struct LateAcceptanceArgs {
+    /// The number of iterations a score is remembered for [default: 50]
+    #[arg(long)]
+    pub lahc_history: Option<usize>
+}
+
+#[derive(Args, Debug)]
+#[command(next_help_heading = "Great deluge")]
+pub struct GreatDelugeArgs {
+    /// How far above the starting score the threshold starts, as a fraction of that score [default: 0.1]
+    #[arg(long)]
+    pub deluge_headroom: Option<f64>
+}
+
+/// Solver settings read from a file, named as on the command line
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct SolveConfig {
+    pub algorithm: Option<Algorithm>,
+    pub tabu_timeout: Option<usize>,
+    pub tabu_size_adjust: Option<usize>,
+    pub initial_temp: Option<f64>,
+    pub temp_scale: Option<f64>,
+    pub auto_temp: Option<bool>,
+    pub initial_acceptance: Option<f64>,
+    pub final_acceptance: Option<f64>,
+    pub reheat_window: Option<usize>,
+    pub reheat_below: Option<f64>,
+    pub reheat_factor: Option<f64>,
+    pub lahc_history: Option<usize>,
+    pub deluge_headroom: Option<f64>,
+    pub islands: Option<Vec<Algorithm>>,
+    pub migration_interval: Option<usize>
+}
+impl SolveConfig {
+    /// Reads settings from a TOML file
+    fn read(file_name: &str) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(file_name).map_err(|e| format!("cannot read {file_name}: {e}"))?;
+        Ok(toml::from_str(&contents).map_err(|e| format!("invalid config {file_name}: {e}"))?)
+    }
+}
+
+/// Adaptive large neighbourhood search also uses the simulated annealing temperature
+#[derive(Args, Debug)]
+#[command(next_help_heading = "Adaptive large neighbourhood search")]
+pub struct AlnsArgs {
+    /// Roughly how many stations each destroy operator removes
+    #[arg(long, default_value_t = 3)]
+    pub destroy_size: usize,
+    /// How quickly operator weights follow their recent success, from 0 to 1
+    #[arg(long, default_value_t = 0.2)]
+    pub reaction: f64,
+    /// The number of iterations between updates to the operator weights
+    #[arg(long, default_value_t = 20)]
+    pub segment: usize
+}
+
+/// The genetic algorithm runs for `--max-iterations` generations, stopping after `--stall-limit` generations without a better solution
+#[derive(Args, Debug)]
+#[command(next_help_heading = "Genetic algorithm")]
+pub struct GeneticArgs {
+    /// The number of solutions in each generation
+    #[arg(long, default_value_t = 30)]
+    pub population: usize,
+    /// The number of best solutions carried unchanged into the next generation
+    #[arg(long, default_value_t = 2)]
+    pub elite: usize,
+    /// The number of solutions competing to be each parent
+    #[arg(long, default_value_t = 3)]
+    pub tournament_size: usize,
+    /// The probability each offspring is mutated
+    #[arg(long, default_value_t = 0.3)]
+    pub mutation_chance: f64,
+    /// Improve each offspring with this many iterations of tabu search, making the algorithm memetic
+    #[arg(long, default_value_t = 0)]
+    pub memetic_iterations: usize
+}
+
+/// Each island of the portfolio runs for up to `--max-iterations`, seeded one after another from `--seed`, wrapping around
+#[derive(Args, Debug)]
+#[command(next_help_heading = "Portfolio")]
+pub struct PortfolioArgs {
+    /// The local searches to run, separated by commas, such as `tabu,sa,sa`;
+    /// by default, one per core, going through tabu, sa, lahc, deluge and alns
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub islands: Vec<Algorithm>,
+    /// The number of iterations each island runs before taking the best solution found by any island [default: 50]
+    #[arg(long)]
+    pub migration_interval: Option<usize>
+}
+
+#[derive(Args, Debug)]
+pub struct GenerateArgs {
+    /// Where to write the problem
+    #[arg(long, short)]
+    pub output: String,
+    /// The number of stations, at least two
+    #[arg(long, short)]
+    pub stations: usize,
+    /// The price per train
+    #[arg(long, default_value_t = 1.0)]
+    pub train_price: f64,
+    /// The total budget
+    #[arg(long, default_value_t = 100.0)]
+    pub total_budget: f64,
+    /// Place stations randomly on a plane, basing costs and times on distance
+    #[arg(long)]
+    pub location: bool,
+    /// Seed for the random number generator
+    #[arg(long)]
+    pub seed: Option<u64>
+}
+
+/// Runs the command given on the command line
+pub fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    match cli.command {
+        Command::Solve(args) => solve(*args),
+        Command::Evaluate { problem, solution, report, evaluation } => {
+            let mut problem = parse_problem(&problem)?;
+            problem.validate()?;
+            let solution = parse_solution(&solution, &problem)?;
+            // The stored objective is checked against the evaluation model it was found with, so overrides only change what is reported
+            evaluation.apply(&mut problem);
+            problem.validate()?;
+            let detailed = evaluate_detailed(&problem, &solution.train_lines);
+            println!("objective: {}", detailed.obj_value);
+            println!("unreachable pairs: {}, unserved demand: {}", detailed.unreachable.len(), detailed.unserved_demand);
+            println!("cost: {} / {}", solution.cost(&problem), problem.total_budget);
+            println!("feasible: {}", solution.check_feasibility(&problem));
+            if let Some(file_name) = report {
+                let contents = match Format::from_file_name(&file_name) {
+                    Format::Toml => toml::to_string(&detailed)?,
+                    Format::Json => serde_json::to_string_pretty(&detailed)?
+                };
+                fs::write(file_name, contents)?;
+            }
+            Ok(())
+        }
+        Command::Generate(args) => {
+            let mut rng = args.seed.map_or_else(Rng::new, Rng::with_seed);
+            let problem = if args.location {
+                gen_random_problem_location(args.stations, args.train_price, args.total_budget, &mut rng)
+            } else {
+                gen_random_problem(args.stations, args.train_price, args.total_budget, &mut rng)
+            };
+            problem.validate()?;
+            save_problem(&args.output, &problem)?;
+            Ok(())
+        }
+        Command::Validate { problem, solution } => {
+            let problem = parse_problem(&problem)?;
+            problem.validate()?;
+            if let Some(solution) = solution {
+                parse_solution(&solution, &problem)?.validate(&problem)?;
+            }
+            println!("valid");
+            Ok(())
+        }
+    }
+}
+
+/// Parses an operator's weight, given as `operator=weight`
+fn parse_weight(s: &str) -> Result<(String, f64), String> {
+    let (name, weight) = s.split_once('=').ok_or("expected `operator=weight`")?;
+    let weight: f64 = weight.parse().map_err(|e| format!("invalid weight: {e}"))?;
+    if weight.is_nan() || weight < 0.0 {
+        return Err(format!("weight {weight} is not a non-negative number"));
+    }
+    Ok((name.to_string(), weight))
+}
+
+/// The built-in operators, with the weights given on the command line, leaving out any with a weight of 0
+fn weighted_operators(weights: &[(String, f64)]) -> Result<Vec<WeightedOperator>, Box<dyn Error>> {
+    let mut operators = default_operators();
+    for (name, weight) in weights {
+        let op = operators.iter_mut().find(|op| op.operator.name() == name).ok_or_else(|| format!(
+            "unknown operator `{name}`; expected one of {}",
+            default_operators().iter().map(|op| op.operator.name().to_string()).collect::<Vec<_>>().join(", ")
+        ))?;
+        op.weight = *weight;
+    }
+    operators.retain(|op| op.weight > 0.0);
+    Ok(operators)
+}
+
+/// The settings every local search is built from
+struct LocalSearchSettings<'a> {
+    problem: &'a Problem,
+    args: &'a SolveArgs,
+    operators: Vec<WeightedOperator>,
+    time_limit: Option<Duration>,
+    cancel: Arc<AtomicBool>
+}
+impl<'a> LocalSearchSettings<'a> {
+    /// A local search with the given metaheuristic, seeded with `seed`
+    fn solver<M: Metaheuristic>(&self, seed: u64, mh_params: M::Params) -> Solver<'a, M> {
+        let args = self.args;
+        Solver {
+            problem: self.problem, max_iterations: args.max_iterations, neighbour_chance: args.neighbour_chance, operators: self.operators.clone(), seed,
+            time_limit: self.time_limit, stall_limit: args.stall_limit, cancel: Some(self.cancel.clone()),
+            evaluation_threads: args.evaluation_threads, neighbour_threads: args.neighbour_threads,
+            verify_delta: args.verify_delta,
+            mh_params
+        }
+    }
+    /// Adaptive large neighbourhood search, which makes its own moves, so needs no neighbourhood operators
+    fn alns(&self, seed: u64, mh_params: AlnsParams) -> Solver<'a, Alns> {
+        Solver { operators: vec![], ..self.solver(seed, mh_params) }
+    }
+}
+
+/// The name of an algorithm, as given on the command line
+fn algorithm_name(algorithm: Algorithm) -> String {
+    // UNWRAP: no algorithm is skipped on the command line
+    algorithm.to_possible_value().unwrap().get_name().to_string()
+}
+
+/// Runs a local search, from `initial` if given, or several times if asked to,
+/// printing the spread of scores across the starts
+fn run_local_search<M: Metaheuristic>(
+    solver: Solver<'_, M>, initial: Option<&Solution>, args: &MultiStartArgs, observer: &mut dyn Observer
+) -> Result<SolveOutcome, Box<dyn Error>> {
+    if args.perturbation.is_some() && solver.operators.is_empty() {
+        return Err("--perturbation needs neighbourhood operators to perturb with, so cannot be used with alns".into());
+    }
+    let multiple = args.starts > 1 || args.perturbation.is_some();
+    match initial {
+        Some(_) if multiple => return Err("an initial solution cannot be used with multiple starts".into()),
+        Some(initial) => return Ok(solver.solve_from_observed(initial, observer)?),
+        None if !multiple => return Ok(solver.solve_observed(observer)),
+        None => {}
+    }
+    let iterated = args.perturbation.map(|strength| IteratedParams {
+        strength,
+        acceptance: match args.ils_accept {
+            IlsAccept::Better => IlsAcceptance::Better,
+            IlsAccept::Always => IlsAcceptance::Always,
+            IlsAccept::NearBest => IlsAcceptance::NearBest(args.ils_near_best)
+        }
+    });
+    let multistart = MultiStart { local_search: solver, starts: args.starts, iterated }.solve_observed(observer);
+    if let Some(summary) = ScoreSummary::new(&multistart.scores) {
+        eprintln!("{} starts: {summary}", multistart.scores.len());
+    }
+    Ok(multistart.outcome)
+}
+
+/// Runs the chosen solver, then writes out its solution
+fn solve(args: SolveArgs) -> Result<(), Box<dyn Error>> {
+    let seed = args.seed.unwrap_or_else(|| fastrand::u64(..));
+    let mut problem = parse_problem(&args.problem)?;
+    problem.validate()?;
+    // Any stored objective is checked against the evaluation model it was found with, before any overrides
+    let initial = args.initial.as_deref().map(|file_name| parse_solution(file_name, &problem)).transpose()?;
+    // Overrides can make a problem invalid too, such as by charging alternative times it does not have
+    args.evaluation.apply(&mut problem);
+    problem.validate()?;
+    let operators = weighted_operators(&args.weights)?;
+    let time_limit = args.time_limit.map(Duration::try_from_secs_f64).transpose()
+        .map_err(|e| format!("invalid time limit: {e}"))?;
+    // Ctrl-C stops the search early, still writing the best solution found
+    let cancel = CANCEL.clone();
+    cancel.store(false, Ordering::Relaxed);
+
+    let progress = |event: &SearchEvent<'_>| match *event {
+        SearchEvent::Iteration { iteration, current_score, best_score, operator, status, .. }
+            if args.progress.is_some_and(|every| every > 0 && iteration % every == 0) => {
+            let operator = operator.unwrap_or("no move");
+            eprintln!("{iteration}: current {current_score}, best {best_score}, {operator}, {status}");
+        }
+        SearchEvent::Restart { iteration, current_score } if args.progress.is_some() => {
+            eprintln!("{iteration}: restarted from {current_score}");
+        }
+        _ => {}
+    };
+    let mut trace = args.trace.as_deref().map(|file_name| Trace::create(file_name, args.trace_every)).transpose()?;
+    // Settings on the command line take precedence over the config file, which takes precedence over the defaults
+    let config = args.config.as_deref().map(SolveConfig::read).transpose()?.unwrap_or_default();
+    let algorithm = args.algorithm.or(config.algorithm).unwrap_or(Algorithm::Tabu);
+    let tabu = TabuParams {
+        initial_timeout: args.tabu.tabu_timeout.or(config.tabu_timeout).unwrap_or(1000),
+        size_adjust: args.tabu.tabu_size_adjust.or(config.tabu_size_adjust).unwrap_or(10)
+    };
+    let initial_temp = args.sa.initial_temp.or(config.initial_temp).unwrap_or(540.0);
+    let temp_scale = args.sa.temp_scale.or(config.temp_scale).unwrap_or_else(
+        || (1.0/initial_temp).powf(1.0/args.max_iterations as f64)
+    );
+    let auto_temp = args.sa.auto_temp || config.auto_temp.unwrap_or(false);
+    let calibration = auto_temp.then(|| Calibration {
+        initial_acceptance: args.sa.initial_acceptance.or(config.initial_acceptance).unwrap_or(0.5),
+        final_acceptance: args.sa.final_acceptance.or(config.final_acceptance).unwrap_or(1e-5)
+    });
+    let reheat = args.sa.reheat_window.or(config.reheat_window).map(|window| Reheat {
+        window,
+        min_acceptance: args.sa.reheat_below.or(config.reheat_below).unwrap_or(0.02),
+        factor: args.sa.reheat_factor.or(config.reheat_factor).unwrap_or(2.0)
+    });
+    let lahc = LateAcceptanceParams { history: args.lahc.lahc_history.or(config.lahc_history).unwrap_or(50) };
+    let deluge = GreatDelugeParams { headroom: args.deluge.deluge_headroom.or(config.deluge_headroom).unwrap_or(0.1) };
+    let mut observer = |event: &SearchEvent<'_>| {
+        progress(event);
+        if let Some(trace) = &mut trace {
+            trace.observe(event);
+        }
+    };
+
+    let sa = SimAnnealParams { initial_temp, temp_scale, calibration, reheat };
+    let alns = AlnsParams {
+        destroy: default_destroy_operators(),
+        repair: default_repair_operators(),
+        destroy_size: args.alns.destroy_size,
+        reaction: args.alns.reaction,
+        segment: args.alns.segment,
+        initial_temp,
+        temp_scale
+    };
+    let settings = LocalSearchSettings { problem: &problem, args: &args, operators, time_limit, cancel };
+
+    let local_search = matches!(algorithm, Algorithm::Tabu | Algorithm::Sa | Algorithm::Lahc | Algorithm::Deluge | Algorithm::Alns);
+    if initial.is_some() && !local_search {
+        return Err(format!("{} cannot start from an initial solution; expected a local search", algorithm_name(algorithm)).into());
+    }
+
+    let outcome = match algorithm {
+        Algorithm::Baseline => None,
+        Algorithm::Tabu => Some(run_local_search(settings.solver::<TabuSearch>(seed, tabu), initial.as_ref(), &args.multistart, &mut observer)?),
+        Algorithm::Sa => Some(run_local_search(settings.solver::<SimAnneal>(seed, sa), initial.as_ref(), &args.multistart, &mut observer)?),
+        Algorithm::Lahc => Some(run_local_search(settings.solver::<LateAcceptance>(seed, lahc), initial.as_ref(), &args.multistart, &mut observer)?),
+        Algorithm::Deluge => Some(run_local_search(settings.solver::<GreatDeluge>(seed, deluge), initial.as_ref(), &args.multistart, &mut observer)?),
+        Algorithm::Alns => Some(run_local_search(settings.alns(seed, alns), initial.as_ref(), &args.multistart, &mut observer)?),
+        Algorithm::Genetic => Some(GeneticSolver {
+            local_search: Solver {
+                max_iterations: args.genetic.memetic_iterations,
+                stall_limit: None,
+                ..settings.solver::<TabuSearch>(seed, tabu)
+            },
+            generations: args.max_iterations,
+            stall_generations: args.stall_limit,
+            population_size: args.genetic.population,
+            elite: args.genetic.elite,
+            tournament_size: args.genetic.tournament_size,
+            mutation_chance: args.genetic.mutation_chance
+        }.solve_observed(&mut observer)),
+        Algorithm::Portfolio => {
+            let algorithms = match (args.portfolio.islands.as_slice(), config.islands) {
+                ([], Some(islands)) => islands,
+                ([], None) => {
+                    let cores = thread::available_parallelism().map_or(1, |n| n.get());
+                    [Algorithm::Tabu, Algorithm::Sa, Algorithm::Lahc, Algorithm::Deluge, Algorithm::Alns].into_iter().cycle().take(cores).collect()
+                }
+                (islands, _) => islands.to_vec()
+            };
+            let islands = algorithms.iter().enumerate().map(|(i, &algorithm)| {
+                let seed = seed.wrapping_add(i as u64);
+                let island: Box<dyn Island> = match algorithm {
+                    Algorithm::Tabu => Box::new(settings.solver::<TabuSearch>(seed, tabu)),
+                    Algorithm::Sa => Box::new(settings.solver::<SimAnneal>(seed, sa)),
+                    Algorithm::Lahc => Box::new(settings.solver::<LateAcceptance>(seed, lahc)),
+                    Algorithm::Deluge => Box::new(settings.solver::<GreatDeluge>(seed, deluge)),
+                    Algorithm::Alns => Box::new(settings.alns(seed, alns.clone())),
+                    Algorithm::Baseline | Algorithm::Genetic | Algorithm::Portfolio => {
+                        return Err(format!("{} cannot be an island of a portfolio; expected a local search", algorithm_name(algorithm)));
+                    }
+                };
+                Ok((algorithm_name(algorithm), island))
+            }).collect::<Result<Vec<_>, _>>()?;
+            if islands.is_empty() {
+                return Err("a portfolio needs at least one island".into());
+            }
+            let portfolio = Portfolio {
+                islands,
+                migration_interval: args.portfolio.migration_interval.or(config.migration_interval).unwrap_or(50)
+            }.solve_observed(&mut observer);
+            for (i, island) in portfolio.islands.iter().enumerate() {
+                eprintln!(
+                    "island {i} ({}): best {}, {} iterations, {} migrations in, led {} times, {}",
+                    island.name, island.best_score, island.iterations, island.migrations, island.leading, island.stop_reason
+                );
+            }
+            Some(portfolio.outcome)
+        }
+    };
+    if let Some(trace) = trace {
+        trace.finish()?;
+    }
+    let solution = match outcome {
+        Some(SolveOutcome { solution, stop_reason, iterations }) => {
+            let iterations = if algorithm == Algorithm::Portfolio {format!("{iterations} migrations")} else {format!("{iterations} iterations")};
+            eprintln!("search {stop_reason} after {iterations}");
+            solution
+        }
+        None => big_loop(&problem, args.schedule.into())
+    };
+
+    eprintln!(
+        "objective: {}, cost: {} / {}, feasible: {}, seed: {seed}",
+        solution.obj_value, solution.cost(&problem), problem.total_budget, solution.check_feasibility(&problem)
+    );
+    match args.output {
+        Some(file_name) => save_solution(&file_name, &solution, &problem)?,
+        None => print!("{}", solution_to_string(&solution, &problem, Format::Toml)?)
+    }
+    Ok(())
+}
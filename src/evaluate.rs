@@ -1,11 +1,15 @@
 //! Evaluates a solution by simulating flow on it
 
+use std::collections::HashMap;
+
 use itertools::Itertools;
-use ndarray::ArrayD;
+use ndarray::{ArrayD, Zip};
 use ordered_float::NotNan;
 use radix_heap::RadixHeapMap;
+use rayon::prelude::*;
+use serde::Serialize;
 
-use crate::problem::{Problem, ScheduleType, TrainLine};
+use crate::problem::{EvaluationModel, Problem, ScheduleType, TrainLine, UnservedPolicy, DEFAULT_TRAVEL_TIME};
 use ScheduleType::*;
 
 
@@ -35,8 +39,10 @@ struct QueueNode {
     /// Tracks if the node has just switched,
     /// to avoid an infinte loop of switching tracks
     pub has_switched: bool,
-    /// The total lines travelled so far - max 5
-    pub total_lines: usize
+    /// The total lines travelled so far, bounded by the maximum number of transfers
+    pub total_lines: usize,
+    /// The index of the node this one was reached from, if journeys are being recorded
+    pub parent: Option<usize>
 }
 impl PartialEq for QueueNode {
     fn eq(&self, other: &Self) -> bool {
@@ -57,141 +63,497 @@ impl Ord for QueueNode {
     }
 }
 
-// Large constant penalty for disconnect between stations
-const DEFAULT_TRAVEL_TIME: f64 = 1e10;
+/// Part of a journey spent on a single train line
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Leg {
+    /// The index of the line ridden
+    pub line: usize,
+    /// The station the line is boarded at
+    pub from: usize,
+    /// The station the line is left at
+    pub to: usize,
+    /// The expected wait for a train on this line
+    pub wait_time: f64,
+    /// Any transfer penalty and walking time before boarding
+    pub transfer_time: f64,
+    /// The time spent on the train
+    pub ride_time: f64
+}
+
+/// The route a commuter takes between two stations
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Journey {
+    pub from: usize,
+    pub to: usize,
+    /// The total travel time
+    pub time: f64,
+    /// The stations where the commuter changes lines
+    pub transfers: Vec<usize>,
+    /// The total time spent waiting for trains
+    pub wait_time: f64,
+    /// The total transfer penalties and walking time
+    pub transfer_time: f64,
+    /// The total time spent on trains
+    pub ride_time: f64,
+    /// Each line ridden, in order
+    pub legs: Vec<Leg>
+}
+impl Journey {
+    fn new(from: usize, to: usize, legs: Vec<Leg>) -> Self {
+        Self {
+            from, to,
+            time: legs.iter().map(|l| l.wait_time + l.transfer_time + l.ride_time).sum(),
+            transfers: legs.iter().skip(1).map(|l| l.from).collect(),
+            wait_time: legs.iter().map(|l| l.wait_time).sum(),
+            transfer_time: legs.iter().map(|l| l.transfer_time).sum(),
+            ride_time: legs.iter().map(|l| l.ride_time).sum(),
+            legs
+        }
+    }
+}
+
+/// A breakdown of an evaluation, explaining where the objective value comes from
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct EvaluationReport {
+    /// The objective value, as returned by `evaluate`
+    pub obj_value: f64,
+    /// The part of the objective from demand that the network serves
+    pub travel_time: f64,
+    /// The total demand between stations that the network does not connect
+    pub unserved_demand: f64,
+    /// The travel time between every pair of stations; unserved pairs take the time charged
+    /// by the unserved-demand policy, or infinity if it does not charge a time
+    pub travel_times: ArrayD<f64>,
+    /// The journey between every connected pair of stations, from the lower numbered station
+    pub journeys: Vec<Journey>,
+    /// Every pair of stations with no journey between them
+    pub unreachable: Vec<(usize, usize)>
+}
+
+/// Stores the nodes processed while evaluating, so journeys can be reconstructed
+#[derive(Debug, Default)]
+struct Recorder {
+    nodes: Vec<QueueNode>,
+    /// The index of the node which first reached each pair of stations
+    claims: HashMap<(usize, usize), usize>
+}
+impl Recorder {
+    /// Stores a node, returning its index
+    fn push(&mut self, node: QueueNode) -> usize {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+    /// The legs ridden to reach the node with index `id`
+    fn legs(&self, id: usize, train_delays: &[f64]) -> Vec<Leg> {
+        let mut path = vec![&self.nodes[id]];
+        while let Some(parent) = path[path.len()-1].parent {
+            path.push(&self.nodes[parent]);
+        }
+        path.reverse();
+
+        let start = path[0];
+        let mut legs = vec![Leg { line: start.train, from: start.station, to: start.station, wait_time: 0.0, transfer_time: 0.0, ride_time: 0.0 }];
+        for (a, b) in path.into_iter().tuple_windows() {
+            // UNWRAP: there is always at least one leg
+            let leg = legs.last_mut().unwrap();
+            if a.station != b.station { // riding the train
+                leg.to = b.station;
+                leg.ride_time += b.score - a.score;
+            } else { // changing lines
+                let wait_time = train_delays[b.train];
+                let mut next = Leg { line: b.train, from: b.station, to: b.station, wait_time, transfer_time: b.score - a.score - wait_time, ride_time: 0.0 };
+                if leg.from == leg.to { // the previous line was never ridden
+                    next.wait_time += leg.wait_time;
+                    next.transfer_time += leg.transfer_time;
+                    *leg = next;
+                } else {
+                    legs.push(next);
+                }
+            }
+        }
+        legs
+    }
+}
 
-/// Evaluates a solution by simulating flow on it
+/// Evaluates a solution by simulating flow on it, using the problem's evaluation model
 /// 
 /// For every station, paths to every other one required are computed via BFS.
 pub fn evaluate(
     problem: &Problem,
     train_lines: &[TrainLine]
 ) -> f64 {
-    // Create a list of E(X_i) where X_i is the time it takes to wait for train i to reach a commuter
-    // This is half the total distance of a cycle over the number of trains on the line
-    let train_delays = train_lines.iter().map(|line| {
+    evaluate_with(problem, train_lines, &problem.evaluation)
+}
+
+/// Evaluates a solution by simulating flow on it, using the given evaluation model
+/// in place of the problem's
+pub fn evaluate_with(
+    problem: &Problem,
+    train_lines: &[TrainLine],
+    model: &EvaluationModel
+) -> f64 {
+    let (station_travel_times, _) = travel_times(problem, train_lines, model, &train_delays(problem, train_lines), Execution::Serial);
+    objective(problem, model, station_travel_times).0.obj_value
+}
+
+/// Evaluates a solution as `evaluate` does, running the searches from each station
+/// in parallel on the current thread pool. The result is identical to `evaluate`.
+pub fn evaluate_parallel(
+    problem: &Problem,
+    train_lines: &[TrainLine]
+) -> f64 {
+    let model = &problem.evaluation;
+    let (station_travel_times, _) = travel_times(problem, train_lines, model, &train_delays(problem, train_lines), Execution::Parallel);
+    objective(problem, model, station_travel_times).0.obj_value
+}
+
+/// How the searches from each station are run: since each is independent,
+/// they may be split across a thread pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Execution {
+    /// One after another on the current thread
+    #[default]
+    Serial,
+    /// In parallel on the current `rayon` thread pool
+    Parallel
+}
+
+/// An evaluation of a solution that keeps what is needed to quickly evaluate similar solutions.
+/// The search from each station only depends on the lines through the stations it processes,
+/// so when lines change, only searches which processed a station on them are repeated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvaluationState {
+    /// The lines of the evaluated solution
+    train_lines: Vec<TrainLine>,
+    /// The travel time between every pair of stations, infinite if unreachable
+    travel_times: ArrayD<f64>,
+    /// Which stations the search from each station processed
+    processed: Vec<Vec<bool>>,
+    /// The objective value, as returned by `evaluate`
+    obj_value: f64
+}
+impl EvaluationState {
+    /// Fully evaluates a solution, using the problem's evaluation model
+    pub fn new(problem: &Problem, train_lines: &[TrainLine], execution: Execution) -> Self {
+        let model = &problem.evaluation;
+        let (travel_times, processed) = travel_times(problem, train_lines, model, &train_delays(problem, train_lines), execution);
+        let obj_value = objective(problem, model, travel_times.clone()).0.obj_value;
+        Self { train_lines: train_lines.to_vec(), travel_times, processed, obj_value }
+    }
+
+    /// The objective value of the evaluated solution
+    pub fn obj_value(&self) -> f64 {
+        self.obj_value
+    }
+
+    /// Evaluates a solution with different lines to this one, only repeating the searches
+    /// affected by the lines which differ. The result is identical to a full evaluation.
+    pub fn delta(&self, problem: &Problem, train_lines: &[TrainLine], execution: Execution) -> Self {
+        // Lines are compared by index, since the search depends on the order of lines
+        let mut changed_stations = vec![false; problem.n];
+        for i in 0..self.train_lines.len().max(train_lines.len()) {
+            let (old, new) = (self.train_lines.get(i), train_lines.get(i));
+            if old == new {continue};
+            for s in old.into_iter().chain(new).flat_map(|l| &l.route) {
+                changed_stations[*s] = true;
+            }
+        }
+
+        let model = &problem.evaluation;
+        let train_delays = train_delays(problem, train_lines);
+        let mut travel_times = self.travel_times.clone();
+        let mut processed = self.processed.clone();
+        let affected = (0..problem.n).filter(|&station| {
+            changed_stations[station]
+                || processed[station].iter().zip(&changed_stations).any(|(&p, &c)| p && c)
+        }).collect_vec();
+        let searches = search_stations(&affected, problem, train_lines, model, &train_delays, execution);
+        for (&station, (times, p)) in affected.iter().zip(searches) {
+            fill_row(&mut travel_times, station, &times);
+            processed[station] = p;
+        }
+        let obj_value = objective(problem, model, travel_times.clone()).0.obj_value;
+        Self { train_lines: train_lines.to_vec(), travel_times, processed, obj_value }
+    }
+}
+
+/// The objective value, split into the parts from served and unserved demand
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Objective {
+    obj_value: f64,
+    travel_time: f64,
+    unserved_demand: f64
+}
+
+/// Calculates the objective from the travel times, where unserved pairs of stations have infinite time,
+/// counting unserved demand according to the model. Returns the travel times actually charged.
+fn objective(problem: &Problem, model: &EvaluationModel, mut station_travel_times: ArrayD<f64>) -> (Objective, ArrayD<f64>) {
+    let mut travel_time = 0.0;
+    let mut unserved_demand = 0.0;
+    Zip::from(&station_travel_times).and(&problem.travel_frequencies).for_each(|&t, &f| {
+        if t.is_finite() {
+            travel_time += t * f;
+        } else {
+            unserved_demand += f;
+        }
+    });
+    travel_time /= 2.0;
+    unserved_demand /= 2.0;
+
+    let obj_value = match model.unserved {
+        UnservedPolicy::Hard => if unserved_demand > 0.0 {f64::INFINITY} else {travel_time},
+        UnservedPolicy::Penalty { weight } => {
+            station_travel_times.mapv_inplace(|t| if t.is_finite() {t} else {weight});
+            // Elementwise multiply with frequencies to get an overall score
+            (&station_travel_times * &problem.travel_frequencies).sum() / 2.0
+        }
+        UnservedPolicy::Alternative { factor } => {
+            match &problem.alternative_times {
+                Some(alternative_times) => Zip::from(&mut station_travel_times).and(alternative_times)
+                    .for_each(|t, &a| if !t.is_finite() {*t = factor * a}),
+                None => station_travel_times.mapv_inplace(|t| if t.is_finite() {t} else {DEFAULT_TRAVEL_TIME})
+            }
+            (&station_travel_times * &problem.travel_frequencies).sum() / 2.0
+        }
+    };
+    (Objective { obj_value, travel_time, unserved_demand }, station_travel_times)
+}
+
+/// Evaluates a solution as `evaluate` does, but also reconstructs the journey between every pair of stations
+pub fn evaluate_detailed(problem: &Problem, train_lines: &[TrainLine]) -> EvaluationReport {
+    evaluate_detailed_with(problem, train_lines, &problem.evaluation)
+}
+
+/// Evaluates a solution as `evaluate_with` does, but also reconstructs the journey between every pair of stations
+pub fn evaluate_detailed_with(
+    problem: &Problem,
+    train_lines: &[TrainLine],
+    model: &EvaluationModel
+) -> EvaluationReport {
+    let train_delays = train_delays(problem, train_lines);
+    let mut recorder = Recorder::default();
+    let travel_times = recorded_travel_times(problem, train_lines, model, &train_delays, &mut recorder);
+    let (Objective { obj_value, travel_time, unserved_demand }, travel_times) = objective(problem, model, travel_times);
+
+    let mut journeys = vec![];
+    let mut unreachable = vec![];
+    for from in 0..problem.n {
+        for to in from+1..problem.n {
+            match recorder.claims.get(&(from, to)) {
+                Some(&id) => journeys.push(Journey::new(from, to, recorder.legs(id, &train_delays))),
+                None => unreachable.push((from, to))
+            }
+        }
+    }
+    EvaluationReport { obj_value, travel_time, unserved_demand, travel_times, journeys, unreachable }
+}
+
+/// Create a list of E(X_i) where X_i is the time it takes to wait for train i to reach a commuter
+/// This is half the total distance of a cycle over the number of trains on the line
+fn train_delays(problem: &Problem, train_lines: &[TrainLine]) -> Vec<f64> {
+    train_lines.iter().map(|line| {
         let mut total_time: f64 = (0..line.route.len()-1).map(|i| problem.track_times[[line.route[i], line.route[i+1]]]).sum();
         if line.ty == Circular { // Must also travel to beginning
             total_time += problem.track_times[[line.route[0], line.route[line.route.len()-1]]];
         }
         total_time / (2.0 * line.n as f64)
-    }).collect_vec();
+    }).collect_vec()
+}
 
-    let mut station_travel_times = ArrayD::<f64>::ones(problem.travel_frequencies.shape()) * DEFAULT_TRAVEL_TIME; // TODO: something more robust
+/// Computes the travel time between every pair of stations, with a search from each station.
+/// Pairs that cannot be reached have infinite time.
+/// Also returns which stations each search processed.
+fn travel_times(
+    problem: &Problem,
+    train_lines: &[TrainLine],
+    model: &EvaluationModel,
+    train_delays: &[f64],
+    execution: Execution
+) -> (ArrayD<f64>, Vec<Vec<bool>>) {
+    let mut station_travel_times = ArrayD::<f64>::from_elem(problem.travel_frequencies.shape(), f64::INFINITY);
+    let stations = (0..problem.n).collect_vec();
+    let mut processed = Vec::with_capacity(problem.n);
+    for (station, (times, p)) in search_stations(&stations, problem, train_lines, model, train_delays, execution).into_iter().enumerate() {
+        fill_row(&mut station_travel_times, station, &times);
+        processed.push(p);
+    }
+    (station_travel_times, processed)
+}
 
-    // Iterate over every starting position
+/// Computes the travel time between every pair of stations as `travel_times` does,
+/// storing the search nodes and how each pair was reached in the recorder
+fn recorded_travel_times(
+    problem: &Problem,
+    train_lines: &[TrainLine],
+    model: &EvaluationModel,
+    train_delays: &[f64],
+    recorder: &mut Recorder
+) -> ArrayD<f64> {
+    let mut station_travel_times = ArrayD::<f64>::from_elem(problem.travel_frequencies.shape(), f64::INFINITY);
     let mut queue = RadixHeapMap::new();
+    let mut times = vec![f64::INFINITY; problem.n];
+    let mut processed = vec![false; problem.n];
     for station in 0..problem.n {
-        queue.clear();
-        // An ordered list for efficient binary search
-        // We only need to visit stations above this one,
-        // since the time from previous stations to this one
-        // has already been calculated
-        let mut stations_unvisited = (station..problem.n).collect_vec();
-        // Storing previous states
-        let mut prev_states = vec![];
-
-        // Start on any train line that goes through this station
-        for (train, line) in train_lines.iter().enumerate().filter(|(_, l)| l.route.contains(&station)) {
-            // UNWRAP: this will never panic: the current station, by use of `filter` above,
-            // will always be in this train's route.
-            let pos = line.route.iter().position(|x| *x == station).unwrap();
-            // UNWRAPS: 0 is not nan
-            queue.push(NotNan::new(0.0).unwrap(), QueueNode {station, train, score: 0.0, direction: Forward, train_schedule_progress: pos, has_switched: false, total_lines: 1});
-            if line.ty == Bidirectional { // could be riding a bidirectional train backwards
-                queue.push(NotNan::new(0.0).unwrap(), QueueNode {station, train, score: 0.0, direction: Backward, train_schedule_progress: pos, has_switched: false, total_lines: 1});
-            }
+        times.fill(f64::INFINITY);
+        search_from(
+            station, problem, train_lines, model, train_delays,
+            &mut queue, &mut times, &mut processed, Some(recorder)
+        );
+        fill_row(&mut station_travel_times, station, &times);
+    }
+    station_travel_times
+}
+
+/// Copies the times from a station's search into both sides of the travel times matrix
+fn fill_row(station_travel_times: &mut ArrayD<f64>, station: usize, times: &[f64]) {
+    for (u, &time) in times.iter().enumerate().skip(station) {
+        station_travel_times[[station, u]] = time;
+        station_travel_times[[u, station]] = time;
+    }
+}
+
+/// Runs the search from each of the given stations, returning the times from each station
+/// and which stations each search processed, in the same order as the stations
+fn search_stations(
+    stations: &[usize],
+    problem: &Problem,
+    train_lines: &[TrainLine],
+    model: &EvaluationModel,
+    train_delays: &[f64],
+    execution: Execution
+) -> Vec<(Vec<f64>, Vec<bool>)> {
+    let search = |queue: &mut RadixHeapMap<NotNan<f64>, QueueNode>, station: usize| {
+        let mut times = vec![f64::INFINITY; problem.n];
+        let mut processed = vec![false; problem.n];
+        search_from(station, problem, train_lines, model, train_delays, queue, &mut times, &mut processed, None);
+        (times, processed)
+    };
+    match execution {
+        Execution::Serial => {
+            let mut queue = RadixHeapMap::new();
+            stations.iter().map(|&station| search(&mut queue, station)).collect()
         }
+        Execution::Parallel => stations.par_iter()
+            .map_init(RadixHeapMap::new, |queue, &station| search(queue, station))
+            .collect()
+    }
+}
 
-        // Algorithm loop, processing the current shortest node
-        while let Some((_, n)) = queue.pop() {
-            if stations_unvisited.is_empty() {break};
-            if let Ok(i) = stations_unvisited.binary_search(&n.station) {
-                station_travel_times[[station, n.station]] = n.score;
-                station_travel_times[[n.station, station]] = n.score;
-                stations_unvisited.remove(i);
-            }
+/// Searches from a single station, filling in `times` to every station numbered at least as high:
+/// the time from previous stations to this one is calculated by their own searches.
+/// Journeys can ride through any station on the way, so no search depends on another's times.
+/// Marks every station the search processes in `processed`.
+#[allow(clippy::too_many_arguments)]
+fn search_from(
+    station: usize,
+    problem: &Problem,
+    train_lines: &[TrainLine],
+    model: &EvaluationModel,
+    train_delays: &[f64],
+    queue: &mut RadixHeapMap<NotNan<f64>, QueueNode>,
+    times: &mut [f64],
+    processed: &mut [bool],
+    mut recorder: Option<&mut Recorder>
+) {
+    queue.clear();
+    // An ordered list for efficient binary search
+    let mut stations_unvisited = (station..problem.n).collect_vec();
+    // Storing previous states
+    let mut prev_states = vec![];
+
+    // Start on any train line that goes through this station
+    for (train, line) in train_lines.iter().enumerate().filter(|(_, l)| l.route.contains(&station)) {
+        // UNWRAP: this will never panic: the current station, by use of `filter` above,
+        // will always be in this train's route.
+        let pos = line.route.iter().position(|x| *x == station).unwrap();
+        // UNWRAPS: 0 is not nan
+        queue.push(NotNan::new(0.0).unwrap(), QueueNode {station, train, score: 0.0, direction: Forward, train_schedule_progress: pos, has_switched: false, total_lines: 1, parent: None});
+        if line.ty == Bidirectional { // could be riding a bidirectional train backwards
+            queue.push(NotNan::new(0.0).unwrap(), QueueNode {station, train, score: 0.0, direction: Backward, train_schedule_progress: pos, has_switched: false, total_lines: 1, parent: None});
+        }
+    }
 
-            match prev_states.binary_search(&(n.station, n.train, n.direction)) {
-                Ok(_) => continue,
-                Err(i) => prev_states.insert(i, (n.station, n.train, n.direction))
+    // Algorithm loop, processing the current shortest node
+    while let Some((_, n)) = queue.pop() {
+        if stations_unvisited.is_empty() {break};
+        processed[n.station] = true;
+        let id = recorder.as_deref_mut().map(|r| r.push(n));
+        if let Ok(i) = stations_unvisited.binary_search(&n.station) {
+            times[n.station] = n.score;
+            stations_unvisited.remove(i);
+            if let (Some(r), Some(id)) = (recorder.as_deref_mut(), id) {
+                r.claims.insert((station, n.station), id);
             }
+        }
 
-            if n.total_lines >= 3 {break};
+        match prev_states.binary_search(&(n.station, n.train, n.direction)) {
+            Ok(_) => continue,
+            Err(i) => prev_states.insert(i, (n.station, n.train, n.direction))
+        }
 
-            // Check if we have already got from this station to targets - if so, visit it!
-            stations_unvisited = stations_unvisited.into_iter().filter(|u| {
-                if station_travel_times[[n.station, *u]] < DEFAULT_TRAVEL_TIME {
-                    station_travel_times[[station, *u]] = n.score + station_travel_times[[n.station, *u]];
-                    station_travel_times[[*u, station]] = n.score + station_travel_times[[n.station, *u]];
-                    return false;
-                }
-                true
-            }).collect();
+        // A commuter could stay on the same train
+        let next_station_pos = match n.direction {
+            Forward => if n.train_schedule_progress + 1 < train_lines[n.train].route.len() {n.train_schedule_progress + 1} else {0},
+            Backward => if n.train_schedule_progress > 0 {n.train_schedule_progress - 1} else {train_lines[n.train].route.len()-1}
+        };
+        let next_station = train_lines[n.train].route[next_station_pos];
+        // Stations already reached can still be ridden through, including those below the source,
+        // which are never targets: `prev_states` stops a state being expanded twice
+        let score = n.score + problem.track_times[[n.station, next_station]];
+        if let Ok(nnan) = NotNan::new(-score) {
+            queue.push(nnan, QueueNode {
+                station: next_station,
+                train: n.train,
+                score,
+                direction: n.direction,
+                train_schedule_progress: next_station_pos,
+                has_switched: false,
+                total_lines: n.total_lines,
+                parent: id
+            });
+        }
 
-            // A commuter could stay on the same train
-            let next_station_pos = match n.direction {
-                Forward => if n.train_schedule_progress + 1 < train_lines[n.train].route.len() {n.train_schedule_progress + 1} else {0},
-                Backward => if n.train_schedule_progress > 0 {n.train_schedule_progress - 1} else {train_lines[n.train].route.len()-1}
+        // A commuter could also switch trains, if they have not run out of transfers
+        if n.has_switched || n.total_lines > model.max_transfers {continue};
+        let transfer_time = model.transfer_penalty + model.transfer_times.as_ref().map_or(0.0, |t| t[n.station]);
+        let adjacent_trains = train_lines.iter().enumerate()
+            .filter(
+                |(i, l)| *i != n.train && l.route.contains(&n.station) // ensure the train is different to this + visits this station
+            );
+        for (a_train, _) in adjacent_trains {
+            // UNWRAP: again, by the filter above, this will never panic since `position` will always find this station.
+            let pos = match train_lines[a_train].route.iter().position(|x| *x == n.station) {
+                Some(x) => x,
+                None => break // this will never happen
             };
-            let next_station = train_lines[n.train].route[next_station_pos];
-            // only push this node if this station has not yet been visited
-            if stations_unvisited.binary_search(&next_station).is_ok() {
-                let score = n.score + problem.track_times[[n.station, next_station]];
-                if let Ok(nnan) = NotNan::new(-score) {
-                    queue.push(nnan, QueueNode {
-                        station: next_station,
-                        train: n.train,
-                        score,
-                        direction: n.direction,
-                        train_schedule_progress: next_station_pos,
-                        has_switched: false,
-                        total_lines: n.total_lines
-                    });
-                }
+            let score = n.score + transfer_time + train_delays[a_train];
+            if let Ok(nnan) = NotNan::new(-score) {
+                queue.push(nnan, QueueNode {
+                    station: n.station,
+                    train: a_train,
+                    score,
+                    direction: Forward,
+                    train_schedule_progress: pos,
+                    has_switched: true,
+                    total_lines: n.total_lines + 1,
+                    parent: id
+                });
             }
-
-            // A commuter could also switch trains
-            if n.has_switched {continue};
-            let adjacent_trains = train_lines.iter().enumerate()
-                .filter(
-                    |(i, l)| *i != n.train && l.route.contains(&n.station) // ensure the train is different to this + visits this station
-                );
-            for (a_train, _) in adjacent_trains {
-                // UNWRAP: again, by the filter above, this will never panic since `position` will always find this station.
-                let pos = match train_lines[a_train].route.iter().position(|x| *x == n.station) {
-                    Some(x) => x,
-                    None => break // this will never happen
-                };
-                let score = n.score + train_delays[a_train];
+            if train_lines[a_train].ty == Bidirectional { // riding backwards on a bidirectional train
+                let score = n.score + transfer_time + train_delays[a_train];
                 if let Ok(nnan) = NotNan::new(-score) {
                     queue.push(nnan, QueueNode {
                         station: n.station,
                         train: a_train,
                         score,
-                        direction: Forward,
+                        direction: Backward,
                         train_schedule_progress: pos,
                         has_switched: true,
-                        total_lines: n.total_lines + 1
+                        total_lines: n.total_lines + 1,
+                        parent: id
                     });
                 }
-                if train_lines[a_train].ty == Bidirectional { // riding backwards on a bidirectional train
-                    let score = n.score + train_delays[a_train];
-                    if let Ok(nnan) = NotNan::new(-score) {
-                        queue.push(nnan, QueueNode {
-                            station: n.station,
-                            train: a_train,
-                            score,
-                            direction: Backward,
-                            train_schedule_progress: pos,
-                            has_switched: true,
-                            total_lines: n.total_lines + 1
-                        });
-                    }
-                }
             }
         }
     }
-    // Elementwise multiply with frequencies to get an overall score
-    (station_travel_times * &problem.travel_frequencies).sum() / 2.0
-}
\ No newline at end of file
+}
@@ -0,0 +1,48 @@
+//! Generates random problems, for testing and benchmarking solvers
+
+use fastrand::Rng;
+use itertools::Itertools;
+use ndarray::{ArrayD, IxDyn};
+
+use crate::problem::{EvaluationModel, Problem};
+
+/// Copies the entries above the diagonal to below it, so the matrix is symmetric.
+/// Every entry is still drawn, so the random numbers used for everything after are unchanged.
+fn symmetric(mut mat: ArrayD<f64>) -> ArrayD<f64> {
+    let n = mat.shape()[0];
+    for i in 0..n {
+        for j in 0..i {
+            mat[[i, j]] = mat[[j, i]];
+        }
+    }
+    mat
+}
+/// A random symmetric matrix with a zero diagonal
+fn rand_mat(n: usize, rng: &mut Rng) -> ArrayD<f64> {
+    symmetric(ArrayD::from_shape_fn(IxDyn(&[n, n]), |i| if i[0] == i[1] {0.0} else {rng.f64()}))
+}
+/// A random symmetric matrix based on the distance between stations, with noise of magnitude `p`
+fn rand_mat_location(n: usize, x: &[f64], y: &[f64], p: f64, rng: &mut Rng) -> ArrayD<f64> {
+    symmetric(ArrayD::from_shape_fn(IxDyn(&[n, n]), |i| if i[0] == i[1] {0.0} else {
+        ((rng.f64()-0.5)*p + ((x[i[0]]-x[i[1]]).powi(2) + (y[i[0]]-y[i[1]]).powi(2)).sqrt()).max(0.0)
+    }))
+}
+
+/// Generates a problem where all costs, times and frequencies are uniformly random
+pub fn gen_random_problem(n: usize, train_price: f64, total_budget: f64, rng: &mut Rng) -> Problem {
+    let track_costs = rand_mat(n, rng);
+    let track_times = rand_mat(n, rng);
+    let travel_frequencies = rand_mat(n, rng);
+    Problem { n, track_costs, track_times, travel_frequencies, train_price, total_budget, alternative_times: None, evaluation: EvaluationModel::default() }
+}
+
+/// Generates a problem where stations are placed randomly on a unit square,
+/// and costs, times and frequencies are based on the distance between them
+pub fn gen_random_problem_location(n: usize, train_price: f64, total_budget: f64, rng: &mut Rng) -> Problem {
+    let x = (0..n).map(|_| rng.f64()).collect_vec();
+    let y = (0..n).map(|_| rng.f64()).collect_vec();
+    let track_costs = rand_mat_location(n, &x, &y, 0.05, rng);
+    let track_times = rand_mat_location(n, &x, &y, 0.05, rng);
+    let travel_frequencies = rand_mat_location(n, &x, &y, 0.4, rng);
+    Problem { n, track_costs, track_times, travel_frequencies, train_price, total_budget, alternative_times: None, evaluation: EvaluationModel::default() }
+}
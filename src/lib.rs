@@ -0,0 +1,19 @@
+//! Designs train networks: lines over a set of stations which serve the travel demand within a budget.
+//!
+//! The `train-routing` binary is a thin command line client of this library.
+//! Custom neighbourhood operators can be written against [`NeighbourhoodOperator`]
+//! and passed to a [`Solver`] alongside or in place of the defaults.
+
+#![warn(rust_2018_idioms)]
+
+mod baseline;
+pub mod cli;
+pub mod evaluate;
+mod generate;
+pub mod localsearch;
+pub mod parse;
+pub mod problem;
+
+pub use localsearch::{neighbourhood::{Move, NeighbourhoodOperator}, observer, Solver, WorkingSolution};
+
+#[cfg(test)] mod test;
@@ -1,50 +1,59 @@
 //! Implements a local search based algorithm for optimising a train routine.
 
-use std::vec;
+use std::{fmt, hash::{DefaultHasher, Hash, Hasher}, sync::{atomic::{AtomicBool, Ordering}, Arc, OnceLock}, time::{Duration, Instant}, vec};
 
+use fastrand::Rng;
 use itertools::Itertools;
 use ndarray::ArrayD;
+use rayon::{prelude::*, ThreadPoolBuilder};
 
-use crate::{baseline, evaluate::evaluate, problem::{Problem, ScheduleType, Solution, TrainLine}};
+use self::{neighbourhood::{Move, WeightedOperator}, observer::{Observer, SearchEvent}};
+use crate::{baseline, evaluate::{evaluate, evaluate_parallel, EvaluationState, Execution}, problem::{canonical_lines, InvalidSolution, Problem, ScheduleType, Solution, TrainLine, TrainTrackIterator}};
 
+pub mod alns;
+pub mod genetic;
 pub mod metaheuristic;
+pub mod multistart;
+pub mod neighbourhood;
+pub mod observer;
+pub mod portfolio;
+pub mod trace;
 
-/// Helper iterator to visit all tracks on a single train line
-struct TrainTrackIterator<'a> {
-    train_line: &'a TrainLine,
-    i: usize
+/// A hash of a line which is the same for any line running the same service
+fn line_hash(line: &TrainLine) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (line.canonical_route(), line.ty, line.n).hash(&mut hasher);
+    hasher.finish()
 }
-impl<'a> TrainTrackIterator<'a> {
-    /// Create a new track iterator for a specific line
-    pub fn new(train_line: &'a TrainLine) -> Self {
-        Self { train_line, i: 0 }
-    }
-}
-impl<'a> Iterator for TrainTrackIterator<'a> {
-    type Item = (usize, usize); // represents two stations
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.i == self.train_line.route.len() - 1 + match self.train_line.ty {
-            ScheduleType::Bidirectional => 0,
-            ScheduleType::Circular => 1
-        } {
-            return None;
-        }
-        self.i += 1;
-        if self.train_line.ty == ScheduleType::Circular && self.i == self.train_line.route.len() {
-            // UNWRAP: a train line will always have a station
-            return Some((self.train_line.route[0], *self.train_line.route.last().unwrap()));
-        }
-        Some((self.train_line.route[self.i-1], self.train_line.route[self.i]))
-    }
+/// A hash of a solution's lines which is the same for any lines running the same services, in any order.
+/// Like Zobrist hashing, it combines the hashes of the lines so that changing a line only needs that line's hash.
+pub(crate) fn solution_hash(lines: &[TrainLine]) -> u64 {
+    lines.iter().map(line_hash).fold(0, u64::wrapping_add)
 }
 
 /// A possible partial solution that is currently being considered
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct WorkingSolution {
     train_lines: Vec<TrainLine>,
     cost: f64,
     built_tracks: ArrayD<bool>,
+    /// The evaluation of the solution this one is a neighbour of, used to evaluate it incrementally
+    base: Option<Arc<EvaluationState>>,
+    /// The evaluation made when this solution was scored incrementally, kept so that it is not repeated if the solution is chosen
+    evaluation: OnceLock<Arc<EvaluationState>>,
+    /// The name of the move which made this solution from the one it is a neighbour of
+    operator: Option<Arc<str>>,
+    /// What that move changed
+    changes: Changes,
+    /// The hash of the lines, kept up to date as moves are applied
+    hash: u64
+}
+impl PartialEq for WorkingSolution {
+    /// Solutions are equal if their lines run the same services, whatever order the lines are in
+    /// and however their routes are read. Where a solution came from does not matter.
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash && canonical_lines(&self.train_lines) == canonical_lines(&other.train_lines)
+    }
 }
 impl WorkingSolution {
     /// An empty, basic feasible solution
@@ -53,227 +62,333 @@ impl WorkingSolution {
         let base = baseline::big_loop(problem, ScheduleType::Bidirectional);
         let cost = base.cost(problem);
         Self {
+            hash: solution_hash(&base.train_lines),
             train_lines: base.train_lines,
             cost,
-            built_tracks: base.built_tracks
+            built_tracks: base.built_tracks,
+            base: None,
+            evaluation: OnceLock::new(),
+            operator: None,
+            changes: Changes::default()
+        }
+    }
+    /// A solution running these lines, building exactly the tracks they use
+    fn from_lines(problem: &Problem, train_lines: Vec<TrainLine>) -> Self {
+        let mut built_tracks = ArrayD::from_elem(problem.track_costs.shape(), false);
+        let mut cost = train_lines.iter().map(|l| l.n as f64).sum::<f64>() * problem.train_price;
+        for (a, b) in train_lines.iter().flat_map(TrainTrackIterator::new) {
+            if built_tracks[[a, b]] {continue};
+            built_tracks[[a, b]] = true;
+            built_tracks[[b, a]] = true;
+            cost += problem.track_costs[[a, b]];
+        }
+        Self {
+            hash: solution_hash(&train_lines), train_lines, cost, built_tracks,
+            base: None, evaluation: OnceLock::new(), operator: None, changes: Changes::default()
         }
     }
-}   
+}
 impl WorkingSolution {
-    /// Helper function to evaluate objective
+    /// Helper function to evaluate objective, incrementally from the solution
+    /// this is a neighbour of if possible
     fn evaluate<M: Metaheuristic>(&self, solver: &Solver<'_, M>) -> f64 {
-        evaluate(solver.problem, &self.train_lines)
+        match &self.base {
+            Some(base) => {
+                let state = base.delta(solver.problem, &self.train_lines, solver.execution());
+                let score = state.obj_value();
+                if solver.verify_delta {
+                    let full_score = evaluate(solver.problem, &self.train_lines);
+                    assert!(
+                        score == full_score || (score.is_nan() && full_score.is_nan()),
+                        "incremental evaluation gave {score}, but full evaluation gave {full_score} for {:?}", self.train_lines
+                    );
+                }
+                // Scoring the same solution again gives the same evaluation, so only the first is kept
+                let _ = self.evaluation.set(Arc::new(state));
+                score
+            }
+            None => match solver.execution() {
+                Execution::Serial => evaluate(solver.problem, &self.train_lines),
+                Execution::Parallel => evaluate_parallel(solver.problem, &self.train_lines)
+            }
+        }
+    }
+    /// Fully evaluates this solution, or incrementally from the solution it is a neighbour of,
+    /// keeping the state needed to evaluate its own neighbours incrementally.
+    /// If the solution has already been scored incrementally, that evaluation is reused.
+    fn evaluation_state<M: Metaheuristic>(&self, solver: &Solver<'_, M>) -> Arc<EvaluationState> {
+        if let Some(state) = self.evaluation.get() {
+            return state.clone();
+        }
+        Arc::new(match &self.base {
+            Some(base) => base.delta(solver.problem, &self.train_lines, solver.execution()),
+            None => EvaluationState::new(solver.problem, &self.train_lines, solver.execution())
+        })
     }
     /// Helper funcction to check cost
     fn calc_cost<M: Metaheuristic>(&self, solver: &Solver<'_, M>) -> f64 {
         let train_cost = self.train_lines.iter().map(|l| l.n as f64).sum::<f64>() * solver.problem.train_price;
-        (0..solver.problem.n).into_iter().map(|i| {
+        // Each track is counted once, from its lower station
+        (0..solver.problem.n).map(|i| {
             let mut cost = 0.0;
-            /*'tracks:*/ for j in 0..solver.problem.n {
+            for j in i+1..solver.problem.n {
                 if self.built_tracks[[i, j]] {cost += solver.problem.track_costs[[i, j]]};
-                // for l in &self.train_lines {
-                //     for (a, b) in TrainTrackIterator::new(l) {
-                //         if (i == a && j == b) || (i == b && j == a) {
-                //             cost += solver.problem.track_costs[[i, j]];
-                //             continue 'tracks;
-                //         }
-                //     }
-                // }
             }
             cost
         }).sum::<f64>() + train_cost
     }
-    /// Explore neighbours to this solution, by possible allowed moves
-    fn generate_neighbours<M: Metaheuristic>(&self, solver: &Solver<'_, M>) -> Vec<WorkingSolution> {
-        let mut neighbours = vec![];
-        
-        // Clone a line
-        for i in 0..self.train_lines.len() {
-            if fastrand::f64() > solver.neighbour_chance {continue};
-            let mut cloned_lines = self.train_lines.clone();
-            cloned_lines.push(self.train_lines[i].clone());
-            neighbours.push(Self {
-                // The only new cost is building additional trains, since tracks are already built
-                cost: self.cost + self.train_lines[i].n as f64 * solver.problem.train_price,
-                built_tracks: self.built_tracks.clone(),
-                train_lines: cloned_lines,
-            });
+    /// Applies a move made by the named operator, building and removing tracks as needed.
+    /// Only the tracks of the lines the move changes are checked.
+    fn apply(&self, problem: &Problem, mv: Move, operator: Arc<str>) -> Self {
+        let mut train_lines = self.train_lines.clone();
+        let mut changes = Changes { lines_before: self.train_lines.len(), ..Default::default() };
+        // The lines of this solution which were changed or removed, and the indices of the lines which were changed or added.
+        // The first lines in each are the replaced lines, in the same order.
+        let mut old_lines = vec![];
+        let mut new_lines = vec![];
+        let replaced = mv.replace.len();
+        for (i, line) in mv.replace {
+            old_lines.push(&self.train_lines[i]);
+            train_lines[i] = line;
+            new_lines.push(i);
         }
-
-        // Remove a line
-        if self.train_lines.len() > 1 {
-            for i in 0..self.train_lines.len() {
-                if fastrand::f64() > solver.neighbour_chance {continue};
-                let mut cloned_lines = self.train_lines.clone();
-                let removed_line = cloned_lines.swap_remove(i);
-                let mut cloned_build_tracks = self.built_tracks.clone();
-                let mut cost_saved = removed_line.n as f64 * solver.problem.train_price;
-                // Iterate through all tracks and find if any are unnecessary now
-                for i in 0..solver.problem.n {
-                    'tracks: for j in 0..solver.problem.n {
-                        if !cloned_build_tracks[[i, j]] {continue};
-                        for l in &cloned_lines {
-                            for (a, b) in TrainTrackIterator::new(l) {
-                                if (a == i && b == j) || (a == j && b == i) {continue 'tracks;} // another line is using this track
-                            }
-                        }
-                        // If the code reaches here, the track is no longer necessary
-                        cost_saved += solver.problem.track_costs[[i, j]];
-                        cloned_build_tracks[[i, j]] = false;
-                        cloned_build_tracks[[j, i]] = false;
-                    }
-                }
-                neighbours.push(Self {
-                    // Remove the cost of the line and tracks which are no longer needed
-                    cost: self.cost - cost_saved,
-                    built_tracks: self.built_tracks.clone(),
-                    train_lines: cloned_lines,
-                });
+        // Removing from the end first means each index still refers to the same line
+        for i in mv.remove.into_iter().sorted_unstable().rev() {
+            old_lines.push(&self.train_lines[i]);
+            train_lines.swap_remove(i);
+            changes.removed_lines.push(i);
+            let moved = train_lines.len(); // the old index of the line moved into `i`
+            for j in &mut new_lines {
+                if *j == moved {*j = i};
             }
         }
-
-        // Add a stop to a line
-        for i in 0..self.train_lines.len() {
-            let available_stations = (0..solver.problem.n).filter(|x| !self.train_lines[i].route.contains(x)).collect_vec();
-            for s in available_stations {
-                if fastrand::f64() > solver.neighbour_chance {continue};
-                let mut cloned_lines = self.train_lines.clone();
-                let index = fastrand::usize(0..=cloned_lines[i].route.len()); // the place to add the stop
-                cloned_lines[i].route.insert(index, s);
-                let mut cloned_built_tracks = self.built_tracks.clone();
-                let mut additional_cost = 0.0;
-                // Check if we need to build new tracks
-                let mut connections = Vec::with_capacity(2);
-                if index > 0 && !cloned_built_tracks[[index, index-1]] {
-                    connections.push((index, index-1));
-                }
-                if index < cloned_lines[i].route.len()-1 && !cloned_built_tracks[[index, index+1]] {
-                    connections.push((index, index+1))
-                }
-                if (index == 0 || index == cloned_lines[i].route.len()-1) && cloned_lines[i].ty == ScheduleType::Circular {
-                    connections.push((0, cloned_lines[i].route.len()-1));
-                }
-                for (ai, bi) in connections {
-                    let a = cloned_lines[i].route[ai];
-                    let b = cloned_lines[i].route[bi];
-                    if cloned_built_tracks[[a, b]] {continue};
-                    cloned_built_tracks[[a, b]] = true;
-                    cloned_built_tracks[[b, a]] = true;
-                    additional_cost += solver.problem.track_costs[[a, b]];
-                }
-                neighbours.push(Self {
-                    cost: additional_cost, built_tracks: cloned_built_tracks,
-                    train_lines: cloned_lines,
-                });
-            }
+        for line in mv.add {
+            train_lines.push(line);
+            new_lines.push(train_lines.len()-1);
+        }
+        let hash = new_lines.iter().map(|&i| line_hash(&train_lines[i]))
+            .fold(self.hash, u64::wrapping_add)
+            .wrapping_sub(old_lines.iter().map(|&l| line_hash(l)).fold(0, u64::wrapping_add));
+        for (k, &i) in new_lines.iter().enumerate() {
+            let old_route = if k < replaced {old_lines[k].route.as_slice()} else {&[]};
+            let new_route = &train_lines[i].route;
+            changes.added_stops.extend(new_route.iter().filter(|s| !old_route.contains(s)).map(|&s| (i, s)));
+            changes.removed_stops.extend(old_route.iter().filter(|s| !new_route.contains(s)).map(|&s| (i, s)));
         }
 
-        // Remove a stop from a line
-        // A line must cover at least two stations
-        for i in 0..self.train_lines.len() {
-            if self.train_lines[i].route.len() < 3 {continue};
-            for index in 0..self.train_lines[i].route.len() {
-                if fastrand::f64() > solver.neighbour_chance {continue};
-                let mut cloned_lines = self.train_lines.clone();
-                let mut cloned_built_tracks = self.built_tracks.clone();
-                // Check if any tracks are now unnecessary
-                let mut connections = Vec::with_capacity(2);
-                if index > 0 && !cloned_built_tracks[[index, index-1]] {
-                    connections.push((index, index-1));
-                }
-                if index < cloned_lines[i].route.len()-1 && !cloned_built_tracks[[index, index+1]] {
-                    connections.push((index, index+1))
-                }
-                if (index == 0 || index == cloned_lines[i].route.len()-1) && cloned_lines[i].ty == ScheduleType::Circular {
-                    connections.push((0, cloned_lines[i].route.len()-1));
-                }
-                cloned_lines[i].route.remove(index);
-                let mut cost_saved = 0.0;
-                'connections: for (ai, bi) in connections {
-                    let a = self.train_lines[i].route[ai];
-                    let b = self.train_lines[i].route[bi];
-                    for l in &cloned_lines {
-                        for (c, d) in TrainTrackIterator::new(l) {
-                            if (c == a && d == b) || (c == b && d == a) {continue 'connections}; // this track is needed
-                        }
-                    }
-                    cloned_built_tracks[[a, b]] = false;
-                    cloned_built_tracks[[b, a]] = false;
-                    cost_saved += solver.problem.track_costs[[a, b]];
-                }
-                neighbours.push(Self {train_lines: cloned_lines, cost: self.cost-cost_saved, built_tracks: cloned_built_tracks});
+        let mut built_tracks = self.built_tracks.clone();
+        let trains = |lines: &[TrainLine]| lines.iter().map(|l| l.n as f64).sum::<f64>();
+        let mut cost = self.cost + (trains(&train_lines) - trains(&self.train_lines)) * problem.train_price;
+        // Build any tracks the new lines need
+        for &i in &new_lines {
+            for (a, b) in TrainTrackIterator::new(&train_lines[i]) {
+                if built_tracks[[a, b]] {continue};
+                built_tracks[[a, b]] = true;
+                built_tracks[[b, a]] = true;
+                cost += problem.track_costs[[a, b]];
+                changes.added_tracks.push((a.min(b), a.max(b)));
             }
         }
-        
-        // Increase/decrease number of trains on a line
-        for i in 0..self.train_lines.len() {
-            if fastrand::f64() > solver.neighbour_chance {continue};
-            let mut cloned_lines1 = self.train_lines.clone();
-            cloned_lines1[i].n += 1;
-            neighbours.push(Self { train_lines: cloned_lines1, cost: self.cost + solver.problem.train_price, built_tracks: self.built_tracks.clone() });
-            if self.train_lines[i].n > 1 { // only subtract if the line is still running - don't leave a ghost line
-                let mut cloned_lines2 = self.train_lines.clone();
-                cloned_lines2[i].n -= 1;
-                neighbours.push(Self { train_lines: cloned_lines2, cost: self.cost - solver.problem.train_price, built_tracks: self.built_tracks.clone() });
+        // Remove any tracks the old lines used which no line needs any more
+        for line in old_lines {
+            for (a, b) in TrainTrackIterator::new(line) {
+                if !built_tracks[[a, b]] {continue};
+                let needed = train_lines.iter().any(|l| TrainTrackIterator::new(l).any(|(c, d)| (c == a && d == b) || (c == b && d == a)));
+                if needed {continue};
+                built_tracks[[a, b]] = false;
+                built_tracks[[b, a]] = false;
+                cost -= problem.track_costs[[a, b]];
+                changes.removed_tracks.push((a.min(b), a.max(b)));
             }
         }
-
-        // Change the type of a line
-        for i in 0..self.train_lines.len() {
-            if fastrand::f64() > solver.neighbour_chance {continue};
-            let mut cloned_lines = self.train_lines.clone();
-            let mut cloned_built_tracks = self.built_tracks.clone();
-            let mut cost_change = 0.0;
-            let a = cloned_lines[i].route[0];
-            let b = *cloned_lines[i].route.last().unwrap();
-            match cloned_lines[i].ty {
-                ScheduleType::Bidirectional => {
-                    cloned_lines[i].ty = ScheduleType::Circular;
-                    if !cloned_built_tracks[[a, b]] {
-                        cloned_built_tracks[[a, b]] = true;
-                        cloned_built_tracks[[b, a]] = true;
-                        cost_change = solver.problem.track_costs[[a, b]];
-                    }
-                }
-                ScheduleType::Circular => {
-                    cloned_lines[i].ty = ScheduleType::Bidirectional;
-                    // Find if this track is still necessary
-                    let mut found = false;
-                    'search: for l in &cloned_lines {
-                        for (c, d) in TrainTrackIterator::new(l) {
-                            if (c == a && d == b) || (c == b && d == a) {found = true; break 'search;}
-                        }
-                    }
-                    if !found {
-                        cloned_built_tracks[[a, b]] = false;
-                        cloned_built_tracks[[b, a]] = false;
-                        cost_change = -solver.problem.track_costs[[a, b]];
-                    }
-                }
-            }
-            neighbours.push(Self { train_lines: cloned_lines, cost: self.cost + cost_change, built_tracks: cloned_built_tracks });
+        debug_assert_eq!(hash, solution_hash(&train_lines), "the hash of {train_lines:?} was not kept up to date");
+        Self { train_lines, cost, built_tracks, base: None, evaluation: OnceLock::new(), operator: Some(operator), changes, hash }
+    }
+    /// Makes a random move by one of the solver's operators, chosen by weight,
+    /// or `None` if the operator has no moves or the move would be over budget
+    fn random_move<M: Metaheuristic>(&self, solver: &Solver<'_, M>, rng: &mut Rng) -> Option<WorkingSolution> {
+        let total = solver.operators.iter().map(|op| op.weight).sum::<f64>();
+        let mut x = rng.f64() * total;
+        let op = solver.operators.iter().find(|op| {
+            x -= op.weight;
+            x < 0.0
+        })?;
+        let mut moves = op.operator.moves(&self.train_lines, solver.problem, solver.neighbour_chance, rng);
+        if moves.is_empty() {return None};
+        let mv = moves.swap_remove(rng.usize(..moves.len()));
+        let moved = self.apply(solver.problem, mv, op.operator.name().into());
+        (moved.calc_cost(solver) <= solver.problem.total_budget).then_some(moved)
+    }
+    /// Explore neighbours to this solution, by the solver's operators
+    fn generate_neighbours<M: Metaheuristic>(&self, solver: &Solver<'_, M>, rng: &mut Rng) -> Vec<WorkingSolution> {
+        let mut neighbours = vec![];
+        for op in &solver.operators {
+            let name: Arc<str> = op.operator.name().into();
+            let moves = op.operator.moves(&self.train_lines, solver.problem, solver.neighbour_chance * op.weight, rng);
+            neighbours.extend(moves.into_iter().map(|mv| self.apply(solver.problem, mv, name.clone())));
         }
         neighbours
     }
 }
 
+/// What a move changed, for metaheuristics which remember moves rather than whole solutions.
+/// Lines are numbered as they are after the move.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Changes {
+    /// Stations added to lines, as the line and the station
+    pub added_stops: Vec<(usize, usize)>,
+    /// Stations removed from lines which are still running, as the line and the station
+    pub removed_stops: Vec<(usize, usize)>,
+    /// Tracks built, with the lower station first
+    pub added_tracks: Vec<(usize, usize)>,
+    /// Tracks no longer needed, with the lower station first
+    pub removed_tracks: Vec<(usize, usize)>,
+    /// The lines removed, in the order they were removed, each by moving the last line into its place
+    pub removed_lines: Vec<usize>,
+    /// The number of lines before the move
+    pub lines_before: usize
+}
+impl Changes {
+    /// The number a line before the move has after it, or `None` if it was removed
+    pub fn renumber(&self, mut line: usize) -> Option<usize> {
+        let mut lines = self.lines_before;
+        for &removed in &self.removed_lines {
+            lines -= 1;
+            if line == removed {return None};
+            if line == lines {line = removed};
+        }
+        Some(line)
+    }
+}
+
+/// The solution a search is at
+#[derive(Debug, Clone, Copy)]
+pub struct Current<'s> {
+    solution: &'s WorkingSolution,
+    /// The evaluation of the solution, which its neighbours are evaluated incrementally from
+    state: &'s Arc<EvaluationState>,
+    score: f64,
+    /// When the search started, which time limits are measured from
+    started: Instant
+}
+impl Current<'_> {
+    /// Applies a move to the current solution, to make a neighbour which can be evaluated incrementally
+    fn neighbour(&self, problem: &Problem, mv: Move, operator: Arc<str>) -> WorkingSolution {
+        let mut neighbour = self.solution.apply(problem, mv, operator);
+        neighbour.base = Some(self.state.clone());
+        neighbour
+    }
+}
+
+/// The parameter a metaheuristic adapts as it searches
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MetaheuristicStatus {
+    /// The number of iterations a solution stays tabu for
+    TabuTenure(usize),
+    /// The simulated annealing temperature
+    Temperature(f64),
+    /// The number of different solutions in a population
+    Diversity(usize),
+    /// The worst score a neighbour can have and still be accepted
+    Threshold(f64)
+}
+impl fmt::Display for MetaheuristicStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetaheuristicStatus::TabuTenure(tenure) => write!(f, "tabu tenure {tenure}"),
+            MetaheuristicStatus::Temperature(temp) => write!(f, "temperature {temp}"),
+            MetaheuristicStatus::Diversity(distinct) => write!(f, "{distinct} distinct solutions"),
+            MetaheuristicStatus::Threshold(threshold) => write!(f, "threshold {threshold}")
+        }
+    }
+}
+
 /// Defines a metaheuristic - an abstraction
 /// for tabu search, simulated annealing, etc.
-pub(crate) trait Metaheuristic {
-    type Params: Clone;
+pub trait Metaheuristic {
+    type Params: Clone + Send + Sync;
 
     /// Construct this metaheuristic from parameters
     fn new(params: Self::Params) -> Self;
 
+    /// The current state of the metaheuristic's own parameters, for observers
+    fn status(&self) -> MetaheuristicStatus;
+
     /// Select a neighbouring candidate, returning it and its score; update the metaheuristic with this information
     fn choose_update(
-        &mut self, candidates: Vec<WorkingSolution>, solver: &Solver<'_, Self>, prev_score: f64, time: usize
+        &mut self, candidates: Vec<WorkingSolution>, current: Current<'_>, solver: &Solver<'_, Self>, time: usize, rng: &mut Rng
     ) -> Option<(WorkingSolution, f64)> where Self: Sized;
+
+    /// Whether `choose_update` chooses from the candidates. If not, it is given none, saving generating the neighbourhood.
+    fn uses_neighbours(&self) -> bool {
+        true
+    }
+}
+
+/// Why a search stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// Ran for the maximum number of iterations
+    MaxIterations,
+    /// Ran out of time
+    TimeLimit,
+    /// The best solution stopped improving
+    NoImprovement,
+    /// The cancellation flag was set
+    Cancelled
+}
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            StopReason::MaxIterations => "reached the maximum iterations",
+            StopReason::TimeLimit => "reached the time limit",
+            StopReason::NoImprovement => "stopped improving",
+            StopReason::Cancelled => "cancelled"
+        })
+    }
+}
+
+/// The result of a search: the best solution found, and why the search stopped
+#[derive(Debug, Clone, PartialEq)]
+pub struct SolveOutcome {
+    pub solution: Solution,
+    pub stop_reason: StopReason,
+    /// The number of iterations run
+    pub iterations: usize
+}
+
+/// A metaheuristic and how long the search has gone without improving, part way through a search,
+/// so that a later search can carry on where it stopped
+#[derive(Debug, Clone)]
+struct Running<M> {
+    mh: M,
+    /// The number of moves made so far
+    time: usize,
+    /// Iterations since the best solution last improved
+    stale_iterations: usize,
+    /// Moves since the current solution last improved, for intensification
+    stale_time: usize,
+    /// Best solutions found along the way, which the search restarts from when it stops improving
+    good_solutions: Vec<WorkingSolution>
+}
+impl<M: Metaheuristic> Running<M> {
+    fn new(params: M::Params) -> Self {
+        Self { mh: M::new(params), time: 0, stale_iterations: 0, stale_time: 0, good_solutions: vec![] }
+    }
+}
+
+/// The best solution a search found, and why it stopped
+#[derive(Debug, Clone)]
+struct SearchResult {
+    solution: WorkingSolution,
+    score: f64,
+    stop_reason: StopReason,
+    iterations: usize
 }
 
 /// A local search solver: given a problem and parameters,
 /// create a solution in the `solve` method.
-/// It is non-deterministic and immutable.
+/// It is immutable, and solving twice with the same seed gives the same solution.
 #[derive(Debug, Clone)]
 pub struct Solver<'a, M: Metaheuristic> {
     /// The actual train problem to solve
@@ -283,54 +398,186 @@ pub struct Solver<'a, M: Metaheuristic> {
     pub max_iterations: usize,
     /// The probability a neighbour is constructed
     pub neighbour_chance: f64,
+    /// The operators making neighbours, each scaling `neighbour_chance` by its weight
+    pub operators: Vec<WeightedOperator>,
+    /// Seed for every random choice the search makes
+    pub seed: u64,
+    /// Stop after this long, if given
+    pub time_limit: Option<Duration>,
+    /// Stop after this many iterations without improving the best solution, if given
+    pub stall_limit: Option<usize>,
+    /// Stop as soon as this is set, for example by another thread
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// The number of threads to split each evaluation across: 1 evaluates on the current thread,
+    /// and 0 uses every core
+    pub evaluation_threads: usize,
+    /// The number of threads to score neighbours across: 1 scores them on the current thread,
+    /// and 0 uses every core. The chosen neighbour does not depend on this.
+    pub neighbour_threads: usize,
+    /// Checks every incremental evaluation against a full evaluation, panicking if they differ.
+    /// This is slow, and only intended for debugging.
+    pub verify_delta: bool,
     /// Metaheuristic params to use for avoiding
     /// local optima
     pub mh_params: M::Params
 }
 impl<'a, M: Metaheuristic> Solver<'a, M> {
-    /// Solve the problem
-    pub fn solve(&self) -> Solution {
-        // Construct a basic feasible solution
-        let mut solution = WorkingSolution::new(self.problem);
+    /// Solve the problem, returning the best solution found once any limit is reached
+    #[allow(unused)]
+    pub fn solve(&self) -> SolveOutcome {
+        self.solve_observed(&mut ())
+    }
+    /// Solve the problem, telling `observer` about the progress of the search
+    pub fn solve_observed(&self, observer: &mut dyn Observer) -> SolveOutcome {
+        // Start from a basic feasible solution
+        self.in_pool(|| self.solve_local(WorkingSolution::new(self.problem), observer))
+    }
+    /// Solve the problem starting from `initial`, such as a previous run's solution or an existing network,
+    /// once it is checked to be valid for the problem.
+    /// Tracks which none of its lines use are not kept.
+    #[allow(unused)]
+    pub fn solve_from(&self, initial: &Solution) -> Result<SolveOutcome, InvalidSolution> {
+        self.solve_from_observed(initial, &mut ())
+    }
+    /// Solve the problem starting from `initial`, telling `observer` about the progress of the search
+    pub fn solve_from_observed(&self, initial: &Solution, observer: &mut dyn Observer) -> Result<SolveOutcome, InvalidSolution> {
+        initial.validate(self.problem)?;
+        let initial = WorkingSolution::from_lines(self.problem, initial.train_lines.clone());
+        Ok(self.in_pool(|| self.solve_local(initial, observer)))
+    }
+    /// Runs `f` on a thread pool with as many threads as the solver needs,
+    /// or on the current thread if it only needs one
+    fn in_pool<R: Send>(&self, f: impl FnOnce() -> R + Send) -> R {
+        let threads = match (self.evaluation_threads, self.neighbour_threads) {
+            (1, 1) => return f(),
+            (0, _) | (_, 0) => 0, // rayon uses every core
+            (a, b) => a.max(b)
+        };
+        ThreadPoolBuilder::new().num_threads(threads).build()
+            .unwrap() // UNWRAP: only fails if the operating system cannot create threads
+            .install(f)
+    }
+    /// How evaluations are run
+    fn execution(&self) -> Execution {
+        if self.evaluation_threads == 1 {Execution::Serial} else {Execution::Parallel}
+    }
+    /// The number of neighbours which can be scored at once
+    fn scoring_batch(&self) -> usize {
+        match self.neighbour_threads {
+            1 => 1,
+            0 => rayon::current_num_threads(),
+            threads => threads.min(rayon::current_num_threads())
+        }
+    }
+    /// Scores each candidate, returning the scores in the same order,
+    /// splitting them across the neighbour threads
+    fn score_all(&self, candidates: &[WorkingSolution]) -> Vec<f64> {
+        let batch = self.scoring_batch();
+        if batch == 1 {
+            return candidates.iter().map(|n| n.evaluate(self)).collect();
+        }
+        let chunk_size = candidates.len().div_ceil(batch).max(1);
+        candidates.par_chunks(chunk_size)
+            .flat_map_iter(|chunk| chunk.iter().map(|n| n.evaluate(self)))
+            .collect()
+    }
+    /// Whether to stop searching, and why
+    fn stop_reason(&self, start: Instant, stale_iterations: usize) -> Option<StopReason> {
+        self.run_stop_reason(start).or_else(|| {
+            self.stall_limit.is_some_and(|limit| stale_iterations >= limit).then_some(StopReason::NoImprovement)
+        })
+    }
+    /// Why the whole run should stop, if it has been cancelled or is out of time
+    fn run_stop_reason(&self, start: Instant) -> Option<StopReason> {
+        if self.cancel.as_ref().is_some_and(|c| c.load(Ordering::Relaxed)) {
+            Some(StopReason::Cancelled)
+        } else if self.time_limit.is_some_and(|limit| start.elapsed() >= limit) {
+            Some(StopReason::TimeLimit)
+        } else {
+            None
+        }
+    }
+    /// Solve the problem from `initial`, on the current thread pool
+    fn solve_local(&self, initial: WorkingSolution, observer: &mut dyn Observer) -> SolveOutcome {
+        let start = Instant::now();
+        let mut rng = Rng::with_seed(self.seed);
+        let mut running = Running::new(self.mh_params.clone());
+        let search = self.search(&mut running, initial, self.max_iterations, start, &mut rng, observer);
+        observer.observe(&SearchEvent::Finished { stop_reason: search.stop_reason, iterations: search.iterations, best_score: search.score });
+        SolveOutcome {
+            solution: Solution { built_tracks: search.solution.built_tracks, train_lines: search.solution.train_lines, obj_value: search.score },
+            stop_reason: search.stop_reason, iterations: search.iterations
+        }
+    }
+    /// Searches from `solution` for up to `max_iterations`, returning the best solution found.
+    /// Time limits are measured from `start`.
+    fn search(
+        &self, running: &mut Running<M>, mut solution: WorkingSolution, max_iterations: usize, start: Instant, rng: &mut Rng, observer: &mut dyn Observer
+    ) -> SearchResult {
+        let mut state = solution.evaluation_state(self);
         let mut best_solution = solution.clone();
-        let mut best_score = solution.evaluate(self);
+        let mut best_score = state.obj_value();
         let mut current_score = best_score;
-        let mut time = 0;
-        let mut stale_time = 0;
-        let mut good_solutions: Vec<WorkingSolution> = vec![];
+        let mut stop_reason = StopReason::MaxIterations;
+        let mut iterations = 0;
 
-        let mut mh = M::new(self.mh_params.clone());
-        for _ in 0..self.max_iterations {
+        let Running { mh, time, stale_iterations, stale_time, good_solutions } = running;
+        while iterations < max_iterations {
+            if let Some(reason) = self.stop_reason(start, *stale_iterations) {
+                stop_reason = reason;
+                break;
+            }
+            iterations += 1;
+            *stale_iterations += 1;
             // Consider possible neighbours to this solution
-            let mut neighbours = solution.generate_neighbours(self);
-            neighbours = neighbours.into_iter().filter(|n| n.calc_cost(self) <= self.problem.total_budget).collect();
-            let (neighbour, score) = match mh.choose_update(neighbours, self, current_score, time) {
+            let mut neighbours = if mh.uses_neighbours() { solution.generate_neighbours(self, rng) } else { vec![] };
+            neighbours.retain(|n| n.calc_cost(self) <= self.problem.total_budget);
+            for n in &mut neighbours {
+                n.base = Some(state.clone());
+            }
+            let current = Current { solution: &solution, state: &state, score: current_score, started: start };
+            let (neighbour, score) = match mh.choose_update(neighbours, current, self, *time, rng) {
                 Some(x) => x,
-                None => continue
+                None => {
+                    observer.observe(&SearchEvent::Iteration {
+                        iteration: iterations, current_score, best_score, operator: None,
+                        status: mh.status(), solution: &solution.train_lines, cost: solution.cost, elapsed: start.elapsed()
+                    });
+                    continue;
+                }
             };
             // Update current solution
             solution = neighbour;
+            state = solution.evaluation_state(self);
+            solution.base = None;
             if score < best_score {
                 best_solution = solution.clone();
                 best_score = score;
+                *stale_iterations = 0;
             }
             // check staleness
             if current_score <= score {
-                stale_time += 1;
+                *stale_time += 1;
             } else {
-                stale_time = 0;
+                *stale_time = 0;
             }
             current_score = score;
-            if stale_time > 20 && !good_solutions.is_empty() { // intensification
-                solution = fastrand::choice(&good_solutions).unwrap().clone(); // UNWRAP: never unwraps since we've checked good solutions
-                current_score = solution.evaluate(self);
-                stale_time = 0;
+            observer.observe(&SearchEvent::Iteration {
+                iteration: iterations, current_score, best_score, operator: solution.operator.as_deref(),
+                status: mh.status(), solution: &solution.train_lines, cost: solution.cost, elapsed: start.elapsed()
+            });
+            if *stale_time > 20 && !good_solutions.is_empty() { // intensification
+                solution = good_solutions[rng.usize(..good_solutions.len())].clone();
+                state = solution.evaluation_state(self);
+                current_score = state.obj_value();
+                *stale_time = 0;
+                observer.observe(&SearchEvent::Restart { iteration: iterations, current_score });
             }
-            if time % 100 == 0 && !good_solutions.contains(&best_solution) {
+            if *time % 100 == 0 && !good_solutions.contains(&best_solution) {
                 good_solutions.push(best_solution.clone());
             }
-            time += 1;
+            *time += 1;
         }
-        Solution { built_tracks: best_solution.built_tracks, train_lines: best_solution.train_lines, obj_value: best_score }
+        SearchResult { solution: best_solution, score: best_score, stop_reason, iterations }
     }
 }
\ No newline at end of file
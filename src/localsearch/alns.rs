@@ -0,0 +1,321 @@
+//! Adaptive large neighbourhood search: destroys part of a solution and repairs it,
+//! favouring the destroy and repair operators which have recently worked well
+
+use std::{collections::HashSet, fmt::Debug, sync::Arc};
+
+use fastrand::Rng;
+use itertools::Itertools;
+
+use crate::problem::{Problem, ScheduleType, TrainLine, TrainTrackIterator};
+
+use super::{neighbourhood::Move, Current, Metaheuristic, MetaheuristicStatus, Solver, WorkingSolution};
+
+/// The reward for a move finding a new best solution
+const NEW_BEST_REWARD: f64 = 33.0;
+/// The reward for a move improving on the current solution
+const IMPROVED_REWARD: f64 = 9.0;
+/// The reward for a worse move which is accepted anyway
+const ACCEPTED_REWARD: f64 = 13.0;
+
+/// Takes stations out of a solution's lines
+pub trait DestroyOperator: Debug + Send + Sync {
+    /// A short name for the operator, for logging
+    fn name(&self) -> &str;
+    /// Removes around `size` stations from `lines`, where a removed line is `None`,
+    /// returning the stations which no line visits any more.
+    /// At least one line must be left, and every line left must visit at least two stations.
+    fn destroy(&self, lines: &mut [Option<TrainLine>], problem: &Problem, size: usize, rng: &mut Rng) -> Vec<usize>;
+}
+
+/// Puts stations back into a solution's lines
+pub trait RepairOperator: Debug + Send + Sync {
+    /// A short name for the operator, for logging
+    fn name(&self) -> &str;
+    /// Inserts `stations` into `lines`, leaving out any which would put the solution over budget
+    fn repair(&self, lines: &mut [Option<TrainLine>], stations: Vec<usize>, problem: &Problem, rng: &mut Rng);
+}
+
+/// Every built-in destroy operator
+pub fn default_destroy_operators() -> Vec<Arc<dyn DestroyOperator>> {
+    vec![Arc::new(RemoveStops), Arc::new(DropLine), Arc::new(DropRegion)]
+}
+/// Every built-in repair operator
+pub fn default_repair_operators() -> Vec<Arc<dyn RepairOperator>> {
+    vec![Arc::new(GreedyInsertion), Arc::new(RegretInsertion)]
+}
+
+/// The stations out of `stations` which no line visits
+fn uncovered(lines: &[Option<TrainLine>], stations: Vec<usize>) -> Vec<usize> {
+    stations.into_iter().unique()
+        .filter(|s| !lines.iter().flatten().any(|l| l.route.contains(s)))
+        .collect()
+}
+
+/// The cost of a solution with these lines, building exactly the tracks they use
+fn lines_cost(problem: &Problem, lines: &[Option<TrainLine>]) -> f64 {
+    let tracks: HashSet<_> = lines.iter().flatten()
+        .flat_map(|l| TrainTrackIterator::new(l).map(|(a, b)| (a.min(b), a.max(b))))
+        .collect();
+    tracks.into_iter().map(|(a, b)| problem.track_costs[[a, b]]).sum::<f64>()
+        + lines.iter().flatten().map(|l| l.n as f64).sum::<f64>() * problem.train_price
+}
+
+/// Removes random stops from lines with more than two stations
+#[derive(Debug, Clone, Copy)]
+pub struct RemoveStops;
+impl DestroyOperator for RemoveStops {
+    fn name(&self) -> &str { "remove-stops" }
+    fn destroy(&self, lines: &mut [Option<TrainLine>], _problem: &Problem, size: usize, rng: &mut Rng) -> Vec<usize> {
+        let mut removed = vec![];
+        for _ in 0..size {
+            let shrinkable = lines.iter().positions(|l| l.as_ref().is_some_and(|l| l.route.len() > 2)).collect_vec();
+            if shrinkable.is_empty() {break};
+            let i = shrinkable[rng.usize(..shrinkable.len())];
+            // UNWRAP: only lines which are present are chosen
+            let line = lines[i].as_mut().unwrap();
+            removed.push(line.route.remove(rng.usize(..line.route.len())));
+        }
+        uncovered(lines, removed)
+    }
+}
+
+/// Removes a random line, as long as another is left
+#[derive(Debug, Clone, Copy)]
+pub struct DropLine;
+impl DestroyOperator for DropLine {
+    fn name(&self) -> &str { "drop-line" }
+    fn destroy(&self, lines: &mut [Option<TrainLine>], _problem: &Problem, _size: usize, rng: &mut Rng) -> Vec<usize> {
+        let present = lines.iter().positions(Option::is_some).collect_vec();
+        if present.len() < 2 {return vec![]};
+        // UNWRAP: only lines which are present are chosen
+        let line = lines[present[rng.usize(..present.len())]].take().unwrap();
+        uncovered(lines, line.route)
+    }
+}
+
+/// Removes every line visiting a region: a random station and the `size` stations nearest to it.
+/// If every line visits the region, the line visiting it least is kept.
+#[derive(Debug, Clone, Copy)]
+pub struct DropRegion;
+impl DestroyOperator for DropRegion {
+    fn name(&self) -> &str { "drop-region" }
+    fn destroy(&self, lines: &mut [Option<TrainLine>], problem: &Problem, size: usize, rng: &mut Rng) -> Vec<usize> {
+        let centre = rng.usize(..problem.n);
+        let region = (0..problem.n)
+            .sorted_by(|&a, &b| problem.track_times[[centre, a]].total_cmp(&problem.track_times[[centre, b]]))
+            .take(size + 1)
+            .collect_vec();
+        let in_region = |l: &TrainLine| l.route.iter().filter(|s| region.contains(s)).count();
+        let mut touching = lines.iter().positions(|l| l.as_ref().is_some_and(|l| in_region(l) > 0)).collect_vec();
+        if touching.len() == lines.iter().flatten().count() {
+            // UNWRAPS: every line is present, and there is at least one
+            let keep = touching.iter().position_min_by_key(|&&i| in_region(lines[i].as_ref().unwrap())).unwrap();
+            touching.remove(keep);
+        }
+        let mut removed = vec![];
+        for i in touching {
+            // UNWRAP: only lines which are present are chosen
+            removed.extend(lines[i].take().unwrap().route);
+        }
+        uncovered(lines, removed)
+    }
+}
+
+/// How much inserting `station` before position `p` of `line` adds to the time to ride along it
+fn insertion_cost(problem: &Problem, line: &TrainLine, station: usize, p: usize) -> f64 {
+    let (route, times) = (&line.route, &problem.track_times);
+    let circular = line.ty == ScheduleType::Circular;
+    let prev = if p > 0 {route.get(p - 1)} else if circular {route.last()} else {None};
+    let next = if p < route.len() {route.get(p)} else if circular {route.first()} else {None};
+    match (prev, next) {
+        (Some(&a), Some(&b)) => times[[a, station]] + times[[station, b]] - times[[a, b]],
+        (Some(&a), None) => times[[a, station]],
+        (None, Some(&b)) => times[[station, b]],
+        (None, None) => 0.0
+    }
+}
+
+/// Every place `station` could be inserted, as the cost, line and position, cheapest first
+fn insertions(problem: &Problem, lines: &[Option<TrainLine>], station: usize) -> Vec<(f64, usize, usize)> {
+    lines.iter().enumerate()
+        .filter_map(|(i, l)| l.as_ref().filter(|l| !l.route.contains(&station)).map(|l| (i, l)))
+        .flat_map(|(i, l)| (0..=l.route.len()).map(move |p| (insertion_cost(problem, l, station, p), i, p)))
+        .sorted_by(|a, b| a.0.total_cmp(&b.0))
+        .collect()
+}
+
+/// Inserts `station` at the cheapest of `options` which stays within budget, if any
+fn insert_within_budget(problem: &Problem, lines: &mut [Option<TrainLine>], station: usize, options: &[(f64, usize, usize)]) {
+    for &(_, i, p) in options {
+        // UNWRAPS: insertions are only into lines which are present
+        lines[i].as_mut().unwrap().route.insert(p, station);
+        if lines_cost(problem, lines) <= problem.total_budget {return};
+        lines[i].as_mut().unwrap().route.remove(p);
+    }
+}
+
+/// Inserts stations with the most demand first, each where it adds the least travel time
+#[derive(Debug, Clone, Copy)]
+pub struct GreedyInsertion;
+impl RepairOperator for GreedyInsertion {
+    fn name(&self) -> &str { "greedy" }
+    fn repair(&self, lines: &mut [Option<TrainLine>], stations: Vec<usize>, problem: &Problem, _rng: &mut Rng) {
+        let demand = |s: usize| problem.travel_frequencies.index_axis(ndarray::Axis(0), s).sum();
+        for station in stations.into_iter().sorted_by(|&a, &b| demand(b).total_cmp(&demand(a))) {
+            let options = insertions(problem, lines, station);
+            insert_within_budget(problem, lines, station, &options);
+        }
+    }
+}
+
+/// Repeatedly inserts the station which would lose the most by not going in its cheapest place:
+/// the one with the largest difference between its cheapest and second cheapest insertions
+#[derive(Debug, Clone, Copy)]
+pub struct RegretInsertion;
+impl RepairOperator for RegretInsertion {
+    fn name(&self) -> &str { "regret" }
+    fn repair(&self, lines: &mut [Option<TrainLine>], mut stations: Vec<usize>, problem: &Problem, _rng: &mut Rng) {
+        while !stations.is_empty() {
+            let options = stations.iter().map(|&s| insertions(problem, lines, s)).collect_vec();
+            let regret = |o: &Vec<(f64, usize, usize)>| match o.as_slice() {
+                [] => f64::NEG_INFINITY,
+                [_] => f64::INFINITY,
+                [best, second, ..] => second.0 - best.0
+            };
+            // UNWRAP: there is at least one station left
+            let k = options.iter().position_max_by(|a, b| regret(a).total_cmp(&regret(b))).unwrap();
+            let station = stations.swap_remove(k);
+            insert_within_budget(problem, lines, station, &options[k]);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AlnsParams {
+    pub destroy: Vec<Arc<dyn DestroyOperator>>,
+    pub repair: Vec<Arc<dyn RepairOperator>>,
+    /// Roughly how many stations each destroy operator removes
+    pub destroy_size: usize,
+    /// How quickly operator weights move towards their recent performance, from 0 to 1
+    pub reaction: f64,
+    /// The number of iterations between updates to the operator weights
+    pub segment: usize,
+    /// The starting temperature, for accepting worse solutions as in simulated annealing
+    pub initial_temp: f64,
+    /// The factor the temperature is scaled by every iteration
+    pub temp_scale: f64
+}
+
+/// How well an operator has been doing
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct OperatorWeight {
+    weight: f64,
+    /// The total reward since the weights were last updated
+    reward: f64,
+    /// The number of times used since the weights were last updated
+    uses: usize
+}
+impl OperatorWeight {
+    fn new() -> Self {
+        Self { weight: 1.0, reward: 0.0, uses: 0 }
+    }
+    /// Moves the weight towards the average reward since the last update
+    fn update(&mut self, reaction: f64) {
+        if self.uses > 0 {
+            self.weight = (1.0 - reaction) * self.weight + reaction * self.reward / self.uses as f64;
+        }
+        self.reward = 0.0;
+        self.uses = 0;
+    }
+}
+
+/// Picks an operator with probability proportional to its weight
+fn roulette(weights: &[OperatorWeight], rng: &mut Rng) -> usize {
+    let total = weights.iter().map(|w| w.weight).sum::<f64>();
+    let mut x = rng.f64() * total;
+    for (i, w) in weights.iter().enumerate() {
+        if x < w.weight {return i};
+        x -= w.weight;
+    }
+    weights.len() - 1
+}
+
+#[derive(Debug, Clone)]
+pub struct Alns {
+    params: AlnsParams,
+    temp: f64,
+    destroy_weights: Vec<OperatorWeight>,
+    repair_weights: Vec<OperatorWeight>,
+    /// The best score seen
+    best_score: f64,
+    iteration: usize
+}
+impl Metaheuristic for Alns {
+    type Params = AlnsParams;
+
+    fn new(params: Self::Params) -> Self {
+        Self {
+            temp: params.initial_temp,
+            destroy_weights: vec![OperatorWeight::new(); params.destroy.len()],
+            repair_weights: vec![OperatorWeight::new(); params.repair.len()],
+            best_score: f64::INFINITY,
+            iteration: 0,
+            params
+        }
+    }
+
+    fn status(&self) -> MetaheuristicStatus {
+        MetaheuristicStatus::Temperature(self.temp)
+    }
+
+    /// Ignores the candidates, making a single neighbour by destroying and repairing the current solution
+    fn choose_update(&mut self, _candidates: Vec<WorkingSolution>, current: Current<'_>, solver: &Solver<'_, Self>, _time: usize, rng: &mut Rng) -> Option<(WorkingSolution, f64)> {
+        self.temp *= self.params.temp_scale;
+        self.best_score = self.best_score.min(current.score);
+        let d = roulette(&self.destroy_weights, rng);
+        let r = roulette(&self.repair_weights, rng);
+
+        let mut lines = current.solution.train_lines.iter().cloned().map(Some).collect_vec();
+        let removed = self.params.destroy[d].destroy(&mut lines, solver.problem, self.params.destroy_size, rng);
+        self.params.repair[r].repair(&mut lines, removed, solver.problem, rng);
+        let mut mv = Move::default();
+        for (i, (old, new)) in current.solution.train_lines.iter().zip(lines).enumerate() {
+            match new {
+                None => mv.remove.push(i),
+                Some(new) if new != *old => mv.replace.push((i, new)),
+                Some(_) => {}
+            }
+        }
+        let name = format!("{}+{}", self.params.destroy[d].name(), self.params.repair[r].name());
+        let candidate = current.neighbour(solver.problem, mv, name.into());
+
+        // Removing a stop can build a track between its neighbours, so the budget can still be broken
+        let score = if candidate.calc_cost(solver) <= solver.problem.total_budget {candidate.evaluate(solver)} else {f64::INFINITY};
+        let accepted = score < current.score || rng.f64() < ((current.score - score) / self.temp).exp();
+        let reward = if score < self.best_score {
+            NEW_BEST_REWARD
+        } else if score < current.score {
+            IMPROVED_REWARD
+        } else if accepted {
+            ACCEPTED_REWARD
+        } else {
+            0.0
+        };
+        self.best_score = self.best_score.min(score);
+        for w in [&mut self.destroy_weights[d], &mut self.repair_weights[r]] {
+            w.reward += reward;
+            w.uses += 1;
+        }
+        self.iteration += 1;
+        if self.iteration.is_multiple_of(self.params.segment.max(1)) {
+            for w in self.destroy_weights.iter_mut().chain(&mut self.repair_weights) {
+                w.update(self.params.reaction);
+            }
+        }
+        accepted.then_some((candidate, score))
+    }
+
+    fn uses_neighbours(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,179 @@
+//! A genetic algorithm over sets of train lines. Offspring take lines from both parents,
+//! are repaired to be within budget, and are mutated by the local search's operators.
+//! Improving each offspring with a short local search makes it a memetic algorithm.
+
+use std::time::Instant;
+
+use fastrand::Rng;
+use itertools::Itertools;
+
+use crate::problem::{Solution, TrainLine};
+
+use super::{observer::{Observer, SearchEvent}, Metaheuristic, MetaheuristicStatus, Running, SolveOutcome, Solver, StopReason, WorkingSolution};
+
+/// A population-based solver, built around a local search solver
+#[derive(Clone)]
+pub struct GeneticSolver<'a, M: Metaheuristic> {
+    /// The local search whose operators mutate offspring, and whose seed, time limit, cancellation and threads are also used here.
+    /// Its `max_iterations` is the number of iterations of local search run on each offspring:
+    /// 0 gives a plain genetic algorithm. Its `stall_limit` only stops those searches.
+    pub local_search: Solver<'a, M>,
+    /// The maximum number of generations to run
+    pub generations: usize,
+    /// Stop after this many generations without finding a better solution
+    pub stall_generations: Option<usize>,
+    /// The number of solutions in each generation
+    pub population_size: usize,
+    /// The number of best solutions carried unchanged into the next generation
+    pub elite: usize,
+    /// The number of solutions competing to be each parent
+    pub tournament_size: usize,
+    /// The probability each offspring is mutated
+    pub mutation_chance: f64
+}
+impl<'a, M: Metaheuristic> GeneticSolver<'a, M> {
+    /// Solve the problem, returning the best solution found once any limit is reached
+    #[allow(unused)]
+    pub fn solve(&self) -> SolveOutcome {
+        self.solve_observed(&mut ())
+    }
+    /// Solve the problem, telling `observer` about each generation.
+    /// The current score of each iteration is the mean score of the population.
+    pub fn solve_observed(&self, observer: &mut dyn Observer) -> SolveOutcome {
+        self.local_search.in_pool(|| self.solve_local(observer))
+    }
+    /// Solve the problem, on the current thread pool
+    fn solve_local(&self, observer: &mut dyn Observer) -> SolveOutcome {
+        let start = Instant::now();
+        let solver = &self.local_search;
+        let mut rng = Rng::with_seed(solver.seed);
+        let size = self.population_size.max(2);
+        let elite = self.elite.min(size - 1);
+
+        // The first solution is a basic feasible one, and the rest random walks from it
+        let mut initial = vec![WorkingSolution::new(solver.problem)];
+        while initial.len() < size {
+            let mut solution = initial[0].clone();
+            for _ in 0..rng.usize(1..=solver.problem.n) {
+                solution = self.mutate(solution, &mut rng);
+            }
+            initial.push(solution);
+        }
+        let mut population = self.improve(initial, start, &mut rng);
+        let mut best = population[0].clone();
+        // Generations since the best solution last improved
+        let mut stale_generations = 0;
+        let mut stop_reason = StopReason::MaxIterations;
+        let mut generations = 0;
+
+        while generations < self.generations {
+            let stalled = self.stall_generations.is_some_and(|limit| stale_generations >= limit);
+            if let Some(reason) = solver.run_stop_reason(start).or(stalled.then_some(StopReason::NoImprovement)) {
+                stop_reason = reason;
+                break;
+            }
+            generations += 1;
+            stale_generations += 1;
+            let mut offspring: Vec<WorkingSolution> = vec![];
+            while offspring.len() < size - elite {
+                let a = self.tournament(population.len(), &mut rng);
+                let b = self.tournament(population.len(), &mut rng);
+                let mut child = self.crossover(&population[a].0, &population[b].0, &mut rng);
+                // Copies of existing solutions are always mutated, to keep the population diverse
+                let copy = offspring.contains(&child) || population.iter().any(|(s, _)| *s == child);
+                if copy || rng.f64() < self.mutation_chance {
+                    child = self.mutate(child, &mut rng);
+                }
+                offspring.push(child);
+            }
+            population.truncate(elite);
+            population.extend(self.improve(offspring, start, &mut rng));
+            population.sort_by(|(_, score1), (_, score2)| score1.total_cmp(score2));
+            if population[0].1 < best.1 {
+                best = population[0].clone();
+                stale_generations = 0;
+            }
+
+            let (current, _) = &population[0];
+            observer.observe(&SearchEvent::Iteration {
+                iteration: generations,
+                current_score: population.iter().map(|(_, score)| score).sum::<f64>() / population.len() as f64,
+                best_score: best.1,
+                operator: None,
+                status: MetaheuristicStatus::Diversity(population.iter().map(|(s, _)| s.hash).unique().count()),
+                solution: &current.train_lines,
+                cost: current.cost,
+                elapsed: start.elapsed()
+            });
+        }
+        observer.observe(&SearchEvent::Finished { stop_reason, iterations: generations, best_score: best.1 });
+        let (solution, score) = best;
+        SolveOutcome {
+            solution: Solution { built_tracks: solution.built_tracks, train_lines: solution.train_lines, obj_value: score },
+            stop_reason, iterations: generations
+        }
+    }
+    /// Scores new solutions, first improving them by local search if this is a memetic algorithm,
+    /// returning them with their scores, best first
+    fn improve(&self, solutions: Vec<WorkingSolution>, start: Instant, rng: &mut Rng) -> Vec<(WorkingSolution, f64)> {
+        let solver = &self.local_search;
+        let mut scored = if solver.max_iterations == 0 {
+            let scores = solver.score_all(&solutions);
+            solutions.into_iter().zip(scores).collect_vec()
+        } else {
+            solutions.into_iter().map(|s| {
+                let search = solver.search(&mut Running::new(solver.mh_params.clone()), s, solver.max_iterations, start, rng, &mut ());
+                (search.solution, search.score)
+            }).collect_vec()
+        };
+        scored.sort_by(|(_, score1), (_, score2)| score1.total_cmp(score2));
+        scored
+    }
+    /// Picks a parent from a population sorted best first, as the best of a few chosen at random
+    fn tournament(&self, population: usize, rng: &mut Rng) -> usize {
+        // UNWRAP: there is always at least one competitor
+        (0..self.tournament_size.max(1)).map(|_| rng.usize(..population)).min().unwrap()
+    }
+    /// Combines two parents, taking each of their lines with equal chance,
+    /// then repairs the offspring to be within budget.
+    /// If it cannot be repaired, the first parent is copied instead.
+    fn crossover(&self, a: &WorkingSolution, b: &WorkingSolution, rng: &mut Rng) -> WorkingSolution {
+        let mut lines = a.train_lines.iter().chain(&b.train_lines)
+            .filter(|_| rng.bool())
+            .unique_by(|l| l.canonical())
+            .cloned()
+            .collect_vec();
+        if lines.is_empty() {
+            lines.push(a.train_lines[rng.usize(..a.train_lines.len())].clone());
+        }
+        self.repair(lines).unwrap_or_else(|| a.clone())
+    }
+    /// Brings lines within budget, first by running fewer trains on the busiest lines,
+    /// then by removing whichever line saves the most. Fails if a single line is still over budget.
+    fn repair(&self, mut lines: Vec<TrainLine>) -> Option<WorkingSolution> {
+        let problem = self.local_search.problem;
+        loop {
+            let solution = WorkingSolution::from_lines(problem, lines);
+            if solution.cost <= problem.total_budget {return Some(solution)};
+            lines = solution.train_lines;
+            if let Some(line) = lines.iter_mut().filter(|l| l.n > 1).max_by_key(|l| l.n) {
+                line.n -= 1;
+                continue;
+            }
+            if lines.len() < 2 {return None};
+            let cost_without = |i: usize| {
+                let mut rest = lines.clone();
+                rest.remove(i);
+                WorkingSolution::from_lines(problem, rest).cost
+            };
+            // UNWRAP: there are at least two lines
+            let i = (0..lines.len()).min_by(|&i, &j| cost_without(i).total_cmp(&cost_without(j))).unwrap();
+            lines.remove(i);
+        }
+    }
+    /// Makes a random move by one of the local search's operators, chosen by weight,
+    /// keeping the solution unchanged if the move would be over budget
+    fn mutate(&self, solution: WorkingSolution, rng: &mut Rng) -> WorkingSolution {
+        solution.random_move(&self.local_search, rng).unwrap_or(solution)
+    }
+}
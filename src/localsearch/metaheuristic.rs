@@ -1,10 +1,11 @@
-//! Defines tabu search and simulated annealing metaheuristics
+//! Defines tabu search, simulated annealing, late acceptance hill climbing and great deluge metaheuristics
 
-use std::collections::HashMap;
+use std::{collections::{HashMap, VecDeque}, time::Instant};
 
-use crate::problem::TrainLine;
+use fastrand::Rng;
+use itertools::Itertools;
 
-use super::{Metaheuristic, Solver, WorkingSolution};
+use super::{Current, Metaheuristic, MetaheuristicStatus, Solver, WorkingSolution};
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub struct TabuParams {
@@ -14,43 +15,86 @@ pub struct TabuParams {
     pub size_adjust: usize
 }
 
+/// Tabu search remembering the attributes of recent moves, rather than whole solutions:
+/// putting a station back on a line it was recently removed from, or rebuilding a recently removed track,
+/// is tabu, unless it finds a new best solution
 #[derive(Debug, Clone)]
 pub struct TabuSearch {
-    /// The tabu list
-    tabu: HashMap<Vec<TrainLine>, usize>,
+    /// When each station was last removed from each line, by line and station
+    stops: HashMap<(usize, usize), usize>,
+    /// When each track was last removed, with the lower station first
+    tracks: HashMap<(usize, usize), usize>,
+    /// When each solution was last visited, by its hash
+    visited: HashMap<u64, usize>,
     /// The configuration of the tabu search
     params: TabuParams,
     /// The current timeout for expiry of tabu
-    tabu_timeout: usize
+    tabu_timeout: usize,
+    /// The best score seen, for aspiration
+    best_score: f64
+}
+impl TabuSearch {
+    /// Whether the move making a candidate is tabu
+    fn is_tabu(&self, candidate: &WorkingSolution) -> bool {
+        let changes = &candidate.changes;
+        changes.added_stops.iter().any(|stop| self.stops.contains_key(stop))
+            || changes.added_tracks.iter().any(|track| self.tracks.contains_key(track))
+            || self.visited.contains_key(&candidate.hash)
+    }
+    /// Remembers the attributes of the move making the chosen candidate
+    fn remember(&mut self, chosen: &WorkingSolution, time: usize) {
+        let changes = &chosen.changes;
+        if !changes.removed_lines.is_empty() {
+            self.stops = self.stops.drain()
+                .filter_map(|((line, station), t)| Some(((changes.renumber(line)?, station), t)))
+                .collect();
+        }
+        self.stops.extend(changes.removed_stops.iter().map(|&stop| (stop, time)));
+        self.tracks.extend(changes.removed_tracks.iter().map(|&track| (track, time)));
+        self.visited.insert(chosen.hash, time);
+    }
 }
 impl Metaheuristic for TabuSearch {
     type Params = TabuParams;
 
     fn new(params: Self::Params) -> Self {
         Self {
-            tabu: HashMap::new(),
+            stops: HashMap::new(),
+            tracks: HashMap::new(),
+            visited: HashMap::new(),
             params,
             tabu_timeout: params.initial_timeout,
+            best_score: f64::INFINITY
         }
     }
 
-    fn choose_update(&mut self, candidates: Vec<WorkingSolution>, solver: &Solver<'_, Self>, prev_score: f64, time: usize) -> Option<(WorkingSolution, f64)> {
-        self.tabu.retain(|_, v| *v + self.tabu_timeout >= time);
-        if let Some((solution, score)) = candidates.into_iter().filter(|c| !self.tabu.contains_key(&c.train_lines)).map(|n| {
-            let score = n.evaluate(solver);
-            (n, score)
-        })
+    fn status(&self) -> MetaheuristicStatus {
+        MetaheuristicStatus::TabuTenure(self.tabu_timeout)
+    }
+
+    fn choose_update(&mut self, candidates: Vec<WorkingSolution>, current: Current<'_>, solver: &Solver<'_, Self>, time: usize, _rng: &mut Rng) -> Option<(WorkingSolution, f64)> {
+        let active = |v: &mut usize| *v + self.tabu_timeout >= time;
+        self.stops.retain(|_, v| active(v));
+        self.tracks.retain(|_, v| active(v));
+        self.visited.retain(|_, v| active(v));
+        self.best_score = self.best_score.min(current.score);
+        // Tabu candidates are scored too, since they are allowed if they would find a new best solution
+        let scores = solver.score_all(&candidates);
+        // Ties go to the first candidate, so the choice is the same however the scoring is split
+        if let Some((solution, score)) = candidates.into_iter().zip(scores)
+            .filter(|(c, score)| *score < self.best_score || !self.is_tabu(c))
             .min_by(|(_, score1), (_, score2)| score1.total_cmp(score2)) {
-                if prev_score < score && self.tabu_timeout > self.params.size_adjust { // decrease tabu: selected neighbour is worse
+                if current.score < score && self.tabu_timeout > self.params.size_adjust { // decrease tabu: selected neighbour is worse
                     self.tabu_timeout -= self.params.size_adjust;
                 } else { // increase tabu: getting better
                     self.tabu_timeout += self.params.size_adjust;
                 }
-                self.tabu.insert(solution.train_lines.clone(), time);
+                self.remember(&solution, time);
+                self.best_score = self.best_score.min(score);
         
                 Some((solution, score))
         } else {
-            self.tabu_timeout -= self.params.size_adjust;
+            self.tabu_timeout = self.tabu_timeout.saturating_sub(self.params.size_adjust);
             None
         }
     }
@@ -60,25 +104,212 @@ impl Metaheuristic for TabuSearch {
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct SimAnnealParams {
     pub initial_temp: f64,
-    pub temp_scale: f64
+    pub temp_scale: f64,
+    /// Calibrates the temperature to the problem instead of using `initial_temp` and `temp_scale`, if given
+    pub calibration: Option<Calibration>,
+    /// Raises the temperature when few neighbours are being accepted, if given
+    pub reheat: Option<Reheat>
 }
+/// Picks the starting temperature from a sample of neighbours of the starting solution,
+/// then cools over the iteration or time limit, whichever will be reached first
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    /// The probability of accepting a typical worse neighbour at the start
+    pub initial_acceptance: f64,
+    /// The probability of accepting a typical worse neighbour at the end
+    pub final_acceptance: f64
+}
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reheat {
+    /// The number of neighbours acceptance is measured over
+    pub window: usize,
+    /// Reheat when fewer than this proportion of the neighbours in the window are accepted
+    pub min_acceptance: f64,
+    /// The factor the temperature is raised by, which wears off over about `window` iterations
+    pub factor: f64
+}
+
+/// The number of neighbours sampled to calibrate the temperature
+const CALIBRATION_SAMPLE: usize = 50;
+
+/// The temperature at which a neighbour worse by the median of `deltas` is accepted with probability `acceptance`.
+/// The median ignores the few neighbours which are far worse, such as those leaving stations unserved.
+fn temperature_for(deltas: &[f64], acceptance: f64) -> f64 {
+    let median = deltas.iter().copied().sorted_by(f64::total_cmp).nth(deltas.len() / 2).unwrap_or(1.0);
+    -median / acceptance.clamp(1e-9, 1.0 - 1e-9).ln()
+}
+
+/// How far through a search is after `iteration` iterations, from 0 to 1, by iterations or time, whichever is further
+fn progress<M: Metaheuristic>(iteration: usize, started: Instant, solver: &Solver<'_, M>) -> f64 {
+    let by_iterations = iteration as f64 / solver.max_iterations.max(1) as f64;
+    let by_time = solver.time_limit.map_or(0.0, |limit| started.elapsed().as_secs_f64() / limit.as_secs_f64());
+    by_iterations.max(by_time).min(1.0)
+}
+
 #[derive(Debug, Clone)]
 pub struct SimAnneal {
+    /// The temperature before any reheating
     temp: f64,
+    /// The factor reheating has raised the temperature by, decaying back towards 1
+    boost: f64,
+    /// The calibrated starting and final temperatures
+    schedule: Option<(f64, f64)>,
+    /// Whether each neighbour recently considered was accepted, for reheating
+    recent: VecDeque<bool>,
+    iteration: usize,
     params: SimAnnealParams
 }
+impl SimAnneal {
+    /// Samples the candidates' differences from the current score, setting the schedule if any are worse.
+    /// Returns the scores of the first candidates, which were sampled, so they are not scored again.
+    fn calibrate(&mut self, calibration: Calibration, candidates: &[WorkingSolution], current: &Current<'_>, solver: &Solver<'_, Self>) -> Vec<f64> {
+        let scores = solver.score_all(&candidates[..candidates.len().min(CALIBRATION_SAMPLE)]);
+        let deltas = scores.iter()
+            .map(|score| score - current.score)
+            .filter(|d| d.is_finite() && *d > 0.0)
+            .collect_vec();
+        if !deltas.is_empty() {
+            let start = temperature_for(&deltas, calibration.initial_acceptance);
+            let end = temperature_for(&deltas, calibration.final_acceptance);
+            self.schedule = Some((start, end));
+        }
+        scores
+    }
+    /// Records whether a neighbour was accepted, reheating if too few recent ones have been
+    fn record(&mut self, accepted: bool) {
+        let Some(reheat) = self.params.reheat else {return};
+        self.recent.push_back(accepted);
+        if self.recent.len() > reheat.window {
+            self.recent.pop_front();
+        }
+        let acceptance = self.recent.iter().filter(|&&a| a).count() as f64 / self.recent.len() as f64;
+        if self.recent.len() == reheat.window && acceptance < reheat.min_acceptance {
+            self.boost *= reheat.factor;
+            self.recent.clear();
+        }
+    }
+}
 impl Metaheuristic for SimAnneal {
     type Params = SimAnnealParams;
     fn new(params: Self::Params) -> Self {
-        Self { temp: params.initial_temp, params }
-    }
-    fn choose_update(&mut self, mut candidates: Vec<WorkingSolution>, solver: &Solver<'_, Self>, prev_score: f64, _time: usize) -> Option<(WorkingSolution, f64)> {
-        self.temp *= self.params.temp_scale;
-        while !candidates.is_empty() {
-            let n = candidates.remove(fastrand::usize(0..candidates.len()));
-            let score = n.evaluate(solver);
-            if score < prev_score || fastrand::f64() < ((prev_score - score) / self.temp).exp() {return Some((n, score))};
+        Self { temp: params.initial_temp, boost: 1.0, schedule: None, recent: VecDeque::new(), iteration: 0, params }
+    }
+    fn status(&self) -> MetaheuristicStatus {
+        MetaheuristicStatus::Temperature(self.temp * self.boost)
+    }
+    fn choose_update(&mut self, mut candidates: Vec<WorkingSolution>, current: Current<'_>, solver: &Solver<'_, Self>, _time: usize, rng: &mut Rng) -> Option<(WorkingSolution, f64)> {
+        // Candidates are considered in a random order, scoring as many at once as there are threads.
+        // Acceptance is still checked one at a time, so the choice is the same however the scoring is split.
+        rng.shuffle(&mut candidates);
+        let sampled = match (self.params.calibration, self.schedule) {
+            (Some(calibration), None) => self.calibrate(calibration, &candidates, &current, solver),
+            _ => vec![]
+        };
+        self.iteration += 1;
+        self.temp = match self.schedule {
+            // Measured from the start of the search, not of calibration, so the time limit ends the schedule too
+            Some((start, end)) => start * (end / start).powf(progress(self.iteration, current.started, solver)),
+            // Until calibrated, cool as if not calibrating
+            None => self.temp * self.params.temp_scale
+        };
+        if let Some(reheat) = self.params.reheat {
+            self.boost = self.boost.powf(1.0 - 1.0 / reheat.window.max(1) as f64);
+        }
+        let temp = self.temp * self.boost;
+        let (sample, rest) = candidates.split_at(sampled.len());
+        sample.iter().zip(sampled)
+            .chain(rest.chunks(solver.scoring_batch()).flat_map(|batch| batch.iter().zip(solver.score_all(batch))))
+            .find(|&(_, score)| {
+                let accepted = score < current.score || rng.f64() < ((current.score - score) / temp).exp();
+                self.record(accepted);
+                accepted
+            })
+            .map(|(n, score)| (n.clone(), score))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LateAcceptanceParams {
+    /// The number of iterations a score is remembered for
+    pub history: usize
+}
+/// Late acceptance hill climbing: accepts a neighbour which is no worse than
+/// the current solution was `history` iterations ago
+#[derive(Debug, Clone)]
+pub struct LateAcceptance {
+    /// The scores of the current solution over the last `history` iterations, filled in on the first iteration
+    history: Vec<f64>,
+    params: LateAcceptanceParams,
+    iteration: usize
+}
+impl Metaheuristic for LateAcceptance {
+    type Params = LateAcceptanceParams;
+    fn new(params: Self::Params) -> Self {
+        Self { history: vec![], params, iteration: 0 }
+    }
+    fn status(&self) -> MetaheuristicStatus {
+        MetaheuristicStatus::Threshold(self.history.get(self.iteration % self.history.len().max(1)).copied().unwrap_or(f64::INFINITY))
+    }
+    fn choose_update(&mut self, mut candidates: Vec<WorkingSolution>, current: Current<'_>, solver: &Solver<'_, Self>, _time: usize, rng: &mut Rng) -> Option<(WorkingSolution, f64)> {
+        if self.history.is_empty() {
+            self.history = vec![current.score; self.params.history.max(1)];
         }
-        None
+        let slot = self.iteration % self.history.len();
+        self.iteration += 1;
+        let threshold = self.history[slot].max(current.score);
+        // As with simulated annealing, candidates are checked one at a time in a random order
+        rng.shuffle(&mut candidates);
+        let chosen = candidates.chunks(solver.scoring_batch())
+            .flat_map(|batch| batch.iter().zip(solver.score_all(batch)))
+            .find(|&(_, score)| score <= threshold)
+            .map(|(n, score)| (n.clone(), score));
+        self.history[slot] = chosen.as_ref().map_or(current.score, |&(_, score)| score);
+        chosen
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GreatDelugeParams {
+    /// How far above the starting score the threshold starts, as a fraction of that score
+    pub headroom: f64
+}
+/// Great deluge threshold accepting: accepts any neighbour scoring below a threshold,
+/// which falls steadily to meet the best score found by the end of the search, by iterations or time
+#[derive(Debug, Clone)]
+pub struct GreatDeluge {
+    /// The worst score accepted, set on the first iteration
+    threshold: Option<f64>,
+    best_score: f64,
+    params: GreatDelugeParams,
+    iteration: usize,
+    /// How far through the search the threshold has fallen for
+    progress: f64
+}
+impl Metaheuristic for GreatDeluge {
+    type Params = GreatDelugeParams;
+    fn new(params: Self::Params) -> Self {
+        Self { threshold: None, best_score: f64::INFINITY, params, iteration: 0, progress: 0.0 }
+    }
+    fn status(&self) -> MetaheuristicStatus {
+        MetaheuristicStatus::Threshold(self.threshold.unwrap_or(f64::INFINITY))
+    }
+    fn choose_update(&mut self, mut candidates: Vec<WorkingSolution>, current: Current<'_>, solver: &Solver<'_, Self>, _time: usize, rng: &mut Rng) -> Option<(WorkingSolution, f64)> {
+        self.best_score = self.best_score.min(current.score);
+        let threshold = *self.threshold.get_or_insert(current.score * (1.0 + self.params.headroom));
+        rng.shuffle(&mut candidates);
+        let chosen = candidates.chunks(solver.scoring_batch())
+            .flat_map(|batch| batch.iter().zip(solver.score_all(batch)))
+            .find(|&(_, score)| score <= threshold || score < current.score)
+            .map(|(n, score)| (n.clone(), score));
+        if let Some((_, score)) = chosen {
+            self.best_score = self.best_score.min(score);
+        }
+        // Fall by the share of the rest of the search this iteration took, to reach the best score at the end
+        self.iteration += 1;
+        let progress = progress(self.iteration, current.started, solver);
+        let fall = if self.progress < 1.0 {(progress - self.progress) / (1.0 - self.progress)} else {1.0};
+        self.threshold = Some(threshold - (threshold - self.best_score).max(0.0) * fall);
+        self.progress = progress;
+        chosen
+    }
+}
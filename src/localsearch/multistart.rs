@@ -0,0 +1,172 @@
+//! Runs a local search several times: from different constructed solutions,
+//! or as iterated local search, perturbing a good solution found and searching again
+
+use std::{fmt, time::Instant};
+
+use fastrand::Rng;
+use itertools::Itertools;
+
+use crate::{baseline::{big_loop, nearest_neighbour_loop, random_loop}, problem::{ScheduleType, Solution}};
+
+use super::{observer::{Observer, SearchEvent}, Metaheuristic, Running, SolveOutcome, Solver, StopReason, WorkingSolution};
+
+/// Which solution iterated local search carries on from after each search
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IlsAcceptance {
+    /// The search's solution, if it is better than the one it was perturbed from
+    Better,
+    /// The search's solution, always
+    Always,
+    /// The search's solution, if it is within this fraction of the best score found
+    NearBest(f64)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IteratedParams {
+    /// The number of random moves made to perturb a solution before searching again
+    pub strength: usize,
+    pub acceptance: IlsAcceptance
+}
+
+/// Runs a local search several times, returning the best solution found
+#[derive(Clone)]
+pub struct MultiStart<'a, M: Metaheuristic> {
+    /// The local search to run, for up to its `max_iterations` each time.
+    /// Its operators also perturb solutions for iterated local search.
+    pub local_search: Solver<'a, M>,
+    /// The number of times to run the local search
+    pub starts: usize,
+    /// Perturb the solution carried on from for every start after the first, if given,
+    /// which needs the local search to have operators to perturb with.
+    /// Otherwise, each start is from a new constructed solution.
+    pub iterated: Option<IteratedParams>
+}
+
+/// A summary of the scores found by each start
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreSummary {
+    pub best: f64,
+    pub median: f64,
+    pub mean: f64,
+    pub worst: f64
+}
+impl ScoreSummary {
+    /// Summarises some scores, or `None` if there are none
+    pub fn new(scores: &[f64]) -> Option<Self> {
+        let sorted = scores.iter().copied().sorted_by(f64::total_cmp).collect_vec();
+        Some(Self {
+            best: *sorted.first()?,
+            median: sorted[sorted.len() / 2],
+            mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+            worst: *sorted.last()?
+        })
+    }
+}
+impl fmt::Display for ScoreSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "best {}, median {}, mean {}, worst {}", self.best, self.median, self.mean, self.worst)
+    }
+}
+
+/// The result of running a local search several times
+#[derive(Debug, Clone, PartialEq)]
+pub struct MultiStartOutcome {
+    /// The best solution over every start, why the last search stopped, and the total iterations
+    pub outcome: SolveOutcome,
+    /// The best score found by each start, in order
+    pub scores: Vec<f64>
+}
+
+impl<'a, M: Metaheuristic> MultiStart<'a, M> {
+    /// Solve the problem, returning the best solution found once every start has run
+    #[allow(unused)]
+    pub fn solve(&self) -> MultiStartOutcome {
+        self.solve_observed(&mut ())
+    }
+    /// Solve the problem, telling `observer` about every iteration of every start, numbered one after another
+    pub fn solve_observed(&self, observer: &mut dyn Observer) -> MultiStartOutcome {
+        self.local_search.in_pool(|| self.solve_local(observer))
+    }
+    /// Solve the problem, on the current thread pool
+    fn solve_local(&self, observer: &mut dyn Observer) -> MultiStartOutcome {
+        let start = Instant::now();
+        let solver = &self.local_search;
+        assert!(
+            self.iterated.is_none() || !solver.operators.is_empty(),
+            "iterated local search needs neighbourhood operators to perturb solutions with"
+        );
+        let mut rng = Rng::with_seed(solver.seed);
+        let mut best: Option<(WorkingSolution, f64)> = None;
+        // The solution iterated local search carries on from
+        let mut incumbent: Option<(WorkingSolution, f64)> = None;
+        let mut scores = vec![];
+        let mut stop_reason = StopReason::MaxIterations;
+        let mut iterations = 0;
+
+        for i in 0..self.starts.max(1) {
+            let initial = match (&self.iterated, &incumbent) {
+                (Some(iterated), Some((solution, _))) => (0..iterated.strength).fold(solution.clone(), |s, _| {
+                    s.random_move(solver, &mut rng).unwrap_or(s)
+                }),
+                _ => self.construct(i, &mut rng)
+            };
+            let best_score = best.as_ref().map_or(f64::INFINITY, |(_, score)| *score);
+            let offset = iterations;
+            // Iterations are numbered across every start, and the best score is the best over every start
+            let mut forward = |event: &SearchEvent<'_>| match *event {
+                SearchEvent::Iteration { iteration, current_score, best_score: search_best, operator, status, solution, cost, elapsed } => {
+                    observer.observe(&SearchEvent::Iteration {
+                        iteration: offset + iteration, current_score, best_score: search_best.min(best_score),
+                        operator, status, solution, cost, elapsed
+                    });
+                }
+                SearchEvent::Restart { iteration, current_score } => {
+                    observer.observe(&SearchEvent::Restart { iteration: offset + iteration, current_score });
+                }
+                SearchEvent::Finished { .. } => {}
+            };
+            let search = solver.search(&mut Running::new(solver.mh_params.clone()), initial, solver.max_iterations, start, &mut rng, &mut forward);
+            iterations += search.iterations;
+            scores.push(search.score);
+            if best.is_none() || search.score < best_score {
+                best = Some((search.solution.clone(), search.score));
+            }
+            let accepted = match (self.iterated.map(|i| i.acceptance), &incumbent) {
+                (Some(IlsAcceptance::Better), Some((_, score))) => search.score < *score,
+                (Some(IlsAcceptance::NearBest(fraction)), Some(_)) => search.score <= best_score.min(search.score) * (1.0 + fraction),
+                _ => true
+            };
+            if accepted {
+                incumbent = Some((search.solution, search.score));
+            }
+            stop_reason = search.stop_reason;
+            // Only limits on the whole run stop every start; running out of iterations or improvements only stops one
+            if matches!(stop_reason, StopReason::TimeLimit | StopReason::Cancelled) {
+                break;
+            }
+        }
+        // UNWRAP: there is always at least one start
+        let (solution, score) = best.unwrap();
+        observer.observe(&SearchEvent::Finished { stop_reason, iterations, best_score: score });
+        MultiStartOutcome {
+            outcome: SolveOutcome {
+                solution: Solution { built_tracks: solution.built_tracks, train_lines: solution.train_lines, obj_value: score },
+                stop_reason, iterations
+            },
+            scores
+        }
+    }
+    /// Constructs the solution for start `i`, going through several constructions so that starts are diverse.
+    /// Constructions over budget are replaced by the big loop the local search usually starts from.
+    fn construct(&self, i: usize, rng: &mut Rng) -> WorkingSolution {
+        let problem = self.local_search.problem;
+        let solution = match i % 4 {
+            0 => big_loop(problem, ScheduleType::Bidirectional),
+            1 => nearest_neighbour_loop(problem, ScheduleType::Bidirectional, rng.usize(..problem.n)),
+            2 => nearest_neighbour_loop(problem, ScheduleType::Circular, rng.usize(..problem.n)),
+            _ => random_loop(problem, ScheduleType::Bidirectional, rng)
+        };
+        let solution = WorkingSolution::from_lines(problem, solution.train_lines);
+        if solution.cost <= problem.total_budget {solution} else {WorkingSolution::new(problem)}
+    }
+}
@@ -0,0 +1,334 @@
+//! Defines the moves the local search makes between neighbouring solutions,
+//! as operators which can be weighted, or replaced with new ones
+
+use std::{fmt::Debug, sync::Arc};
+
+use fastrand::Rng;
+use itertools::Itertools;
+
+use crate::problem::{Problem, ScheduleType, TrainLine};
+
+/// A change to the lines of a solution. Indices refer to the lines before the change.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Move {
+    /// Lines to replace, with their new versions
+    pub replace: Vec<(usize, TrainLine)>,
+    /// Lines to remove
+    pub remove: Vec<usize>,
+    /// New lines to add
+    pub add: Vec<TrainLine>
+}
+impl Move {
+    /// A move replacing a single line
+    pub fn replace(i: usize, line: TrainLine) -> Self {
+        Self { replace: vec![(i, line)], ..Default::default() }
+    }
+}
+
+/// A way of changing a solution to find its neighbours
+pub trait NeighbourhoodOperator: Debug + Send + Sync {
+    /// A short name for the operator, for logging
+    fn name(&self) -> &str;
+    /// Proposes moves from the solution with these lines.
+    /// Each move the operator could make should only be proposed with probability `chance`.
+    fn moves(&self, lines: &[TrainLine], problem: &Problem, chance: f64, rng: &mut Rng) -> Vec<Move>;
+}
+
+/// An operator, with a weight scaling how likely each of its moves is to be considered
+#[derive(Debug, Clone)]
+pub struct WeightedOperator {
+    pub operator: Arc<dyn NeighbourhoodOperator>,
+    pub weight: f64
+}
+
+/// Every built-in operator, each with a weight of 1
+pub fn default_operators() -> Vec<WeightedOperator> {
+    let operators: [Arc<dyn NeighbourhoodOperator>; 13] = [
+        Arc::new(CloneLine), Arc::new(RemoveLine), Arc::new(AddStop), Arc::new(RemoveStop),
+        Arc::new(TwoOpt), Arc::new(OrOpt), Arc::new(Exchange), Arc::new(Relocate), Arc::new(Split), Arc::new(Merge),
+        Arc::new(AddTrain), Arc::new(RemoveTrain), Arc::new(ChangeSchedule)
+    ];
+    operators.into_iter().map(|operator| WeightedOperator { operator, weight: 1.0 }).collect()
+}
+
+/// Run another copy of a line
+/// The only new cost is building additional trains, since tracks are already built
+#[derive(Debug, Clone, Copy)]
+pub struct CloneLine;
+impl NeighbourhoodOperator for CloneLine {
+    fn name(&self) -> &str { "clone-line" }
+    fn moves(&self, lines: &[TrainLine], _problem: &Problem, chance: f64, rng: &mut Rng) -> Vec<Move> {
+        let mut moves = vec![];
+        for line in lines {
+            if rng.f64() > chance {continue};
+            moves.push(Move { add: vec![line.clone()], ..Default::default() });
+        }
+        moves
+    }
+}
+
+/// Remove a line, as long as another is left
+#[derive(Debug, Clone, Copy)]
+pub struct RemoveLine;
+impl NeighbourhoodOperator for RemoveLine {
+    fn name(&self) -> &str { "remove-line" }
+    fn moves(&self, lines: &[TrainLine], _problem: &Problem, chance: f64, rng: &mut Rng) -> Vec<Move> {
+        let mut moves = vec![];
+        if lines.len() < 2 {return moves};
+        for i in 0..lines.len() {
+            if rng.f64() > chance {continue};
+            moves.push(Move { remove: vec![i], ..Default::default() });
+        }
+        moves
+    }
+}
+
+/// Add each station a line does not visit to a random place on it
+#[derive(Debug, Clone, Copy)]
+pub struct AddStop;
+impl NeighbourhoodOperator for AddStop {
+    fn name(&self) -> &str { "add-stop" }
+    fn moves(&self, lines: &[TrainLine], problem: &Problem, chance: f64, rng: &mut Rng) -> Vec<Move> {
+        let mut moves = vec![];
+        for (i, line) in lines.iter().enumerate() {
+            let available_stations = (0..problem.n).filter(|x| !line.route.contains(x)).collect_vec();
+            for s in available_stations {
+                if rng.f64() > chance {continue};
+                let mut line = line.clone();
+                let index = rng.usize(0..=line.route.len()); // the place to add the stop
+                line.route.insert(index, s);
+                moves.push(Move::replace(i, line));
+            }
+        }
+        moves
+    }
+}
+
+/// Remove a stop from a line
+/// A line must cover at least two stations
+#[derive(Debug, Clone, Copy)]
+pub struct RemoveStop;
+impl NeighbourhoodOperator for RemoveStop {
+    fn name(&self) -> &str { "remove-stop" }
+    fn moves(&self, lines: &[TrainLine], _problem: &Problem, chance: f64, rng: &mut Rng) -> Vec<Move> {
+        let mut moves = vec![];
+        for (i, line) in lines.iter().enumerate() {
+            if line.route.len() < 3 {continue};
+            for index in 0..line.route.len() {
+                if rng.f64() > chance {continue};
+                let mut line = line.clone();
+                line.route.remove(index);
+                moves.push(Move::replace(i, line));
+            }
+        }
+        moves
+    }
+}
+
+/// Reverse a segment of a line (2-opt), from each stop to a random later stop
+/// Reversing the whole line only changes its direction, so is skipped
+#[derive(Debug, Clone, Copy)]
+pub struct TwoOpt;
+impl NeighbourhoodOperator for TwoOpt {
+    fn name(&self) -> &str { "2-opt" }
+    fn moves(&self, lines: &[TrainLine], _problem: &Problem, chance: f64, rng: &mut Rng) -> Vec<Move> {
+        let mut moves = vec![];
+        for (i, line) in lines.iter().enumerate() {
+            let len = line.route.len();
+            if len < 3 {continue};
+            for start in 0..len-1 {
+                if rng.f64() > chance {continue};
+                let end = rng.usize(start+1..len);
+                if start == 0 && end == len-1 {continue};
+                let mut line = line.clone();
+                line.route[start..=end].reverse();
+                moves.push(Move::replace(i, line));
+            }
+        }
+        moves
+    }
+}
+
+/// Move a run of one to three stops elsewhere in a line (or-opt), from each stop to a random place
+#[derive(Debug, Clone, Copy)]
+pub struct OrOpt;
+impl NeighbourhoodOperator for OrOpt {
+    fn name(&self) -> &str { "or-opt" }
+    fn moves(&self, lines: &[TrainLine], _problem: &Problem, chance: f64, rng: &mut Rng) -> Vec<Move> {
+        let mut moves = vec![];
+        for (i, line) in lines.iter().enumerate() {
+            let len = line.route.len();
+            if len < 3 {continue};
+            for start in 0..len {
+                if rng.f64() > chance {continue};
+                let run_len = rng.usize(1..=3).min(len - start).min(len - 2);
+                let mut line = line.clone();
+                let run = line.route.drain(start..start+run_len).collect_vec();
+                let index = rng.usize(0..=line.route.len()); // the place to move the run to
+                if index == start {continue};
+                line.route.splice(index..index, run);
+                moves.push(Move::replace(i, line));
+            }
+        }
+        moves
+    }
+}
+
+/// Swap a stop on one line with a random stop on another
+#[derive(Debug, Clone, Copy)]
+pub struct Exchange;
+impl NeighbourhoodOperator for Exchange {
+    fn name(&self) -> &str { "exchange" }
+    fn moves(&self, lines: &[TrainLine], _problem: &Problem, chance: f64, rng: &mut Rng) -> Vec<Move> {
+        let mut moves = vec![];
+        for (i, j) in (0..lines.len()).tuple_combinations() {
+            for p in 0..lines[i].route.len() {
+                if rng.f64() > chance {continue};
+                let q = rng.usize(0..lines[j].route.len());
+                let (a, b) = (lines[i].route[p], lines[j].route[q]);
+                // A line cannot visit a station twice
+                if lines[i].route.contains(&b) || lines[j].route.contains(&a) {continue};
+                let (mut line_i, mut line_j) = (lines[i].clone(), lines[j].clone());
+                line_i.route[p] = b;
+                line_j.route[q] = a;
+                moves.push(Move { replace: vec![(i, line_i), (j, line_j)], ..Default::default() });
+            }
+        }
+        moves
+    }
+}
+
+/// Move a stop from one line to a random place on a random other line
+#[derive(Debug, Clone, Copy)]
+pub struct Relocate;
+impl NeighbourhoodOperator for Relocate {
+    fn name(&self) -> &str { "relocate" }
+    fn moves(&self, lines: &[TrainLine], _problem: &Problem, chance: f64, rng: &mut Rng) -> Vec<Move> {
+        let mut moves = vec![];
+        if lines.len() < 2 {return moves};
+        for i in 0..lines.len() {
+            if lines[i].route.len() < 3 {continue};
+            for p in 0..lines[i].route.len() {
+                if rng.f64() > chance {continue};
+                let j = (i + rng.usize(1..lines.len())) % lines.len();
+                let station = lines[i].route[p];
+                if lines[j].route.contains(&station) {continue};
+                let (mut line_i, mut line_j) = (lines[i].clone(), lines[j].clone());
+                line_i.route.remove(p);
+                let index = rng.usize(0..=line_j.route.len()); // the place to add the stop
+                line_j.route.insert(index, station);
+                moves.push(Move { replace: vec![(i, line_i), (j, line_j)], ..Default::default() });
+            }
+        }
+        moves
+    }
+}
+
+/// Split a line in two at a station, which both halves keep
+/// Each half runs back and forth, and the trains are shared between them, so only lines with at least two trains are split
+#[derive(Debug, Clone, Copy)]
+pub struct Split;
+impl NeighbourhoodOperator for Split {
+    fn name(&self) -> &str { "split" }
+    fn moves(&self, lines: &[TrainLine], _problem: &Problem, chance: f64, rng: &mut Rng) -> Vec<Move> {
+        let mut moves = vec![];
+        for (i, line) in lines.iter().enumerate() {
+            if line.route.len() < 3 || line.n < 2 {continue};
+            for k in 1..line.route.len()-1 {
+                if rng.f64() > chance {continue};
+                moves.push(Move {
+                    replace: vec![(i, TrainLine { route: line.route[..=k].to_vec(), ty: ScheduleType::Bidirectional, n: line.n - line.n/2 })],
+                    add: vec![TrainLine { route: line.route[k..].to_vec(), ty: ScheduleType::Bidirectional, n: line.n/2 }],
+                    ..Default::default()
+                });
+            }
+        }
+        moves
+    }
+}
+
+/// Merge two back and forth lines which share an endpoint, and no other station, keeping all their trains
+#[derive(Debug, Clone, Copy)]
+pub struct Merge;
+impl NeighbourhoodOperator for Merge {
+    fn name(&self) -> &str { "merge" }
+    fn moves(&self, lines: &[TrainLine], _problem: &Problem, chance: f64, rng: &mut Rng) -> Vec<Move> {
+        let mut moves = vec![];
+        for (i, j) in (0..lines.len()).tuple_combinations() {
+            let (a, b) = (&lines[i], &lines[j]);
+            if a.ty != ScheduleType::Bidirectional || b.ty != ScheduleType::Bidirectional {continue};
+            if a.route.iter().filter(|s| b.route.contains(s)).count() != 1 {continue};
+            // UNWRAP: a train line will always have a station
+            let (a_first, a_last) = (a.route[0], *a.route.last().unwrap());
+            let (b_first, b_last) = (b.route[0], *b.route.last().unwrap());
+            let route = if a_last == b_first {
+                a.route.iter().chain(&b.route[1..]).copied().collect_vec()
+            } else if a_last == b_last {
+                a.route.iter().chain(b.route.iter().rev().skip(1)).copied().collect_vec()
+            } else if a_first == b_last {
+                b.route.iter().chain(&a.route[1..]).copied().collect_vec()
+            } else if a_first == b_first {
+                b.route.iter().rev().chain(&a.route[1..]).copied().collect_vec()
+            } else {
+                continue
+            };
+            if rng.f64() > chance {continue};
+            moves.push(Move {
+                replace: vec![(i, TrainLine { route, ty: ScheduleType::Bidirectional, n: a.n + b.n })],
+                remove: vec![j],
+                ..Default::default()
+            });
+        }
+        moves
+    }
+}
+
+/// Run another train on a line
+#[derive(Debug, Clone, Copy)]
+pub struct AddTrain;
+impl NeighbourhoodOperator for AddTrain {
+    fn name(&self) -> &str { "add-train" }
+    fn moves(&self, lines: &[TrainLine], _problem: &Problem, chance: f64, rng: &mut Rng) -> Vec<Move> {
+        let mut moves = vec![];
+        for (i, line) in lines.iter().enumerate() {
+            if rng.f64() > chance {continue};
+            moves.push(Move::replace(i, TrainLine { n: line.n + 1, ..line.clone() }));
+        }
+        moves
+    }
+}
+
+/// Run one fewer train on a line
+/// Only subtract if the line is still running - don't leave a ghost line
+#[derive(Debug, Clone, Copy)]
+pub struct RemoveTrain;
+impl NeighbourhoodOperator for RemoveTrain {
+    fn name(&self) -> &str { "remove-train" }
+    fn moves(&self, lines: &[TrainLine], _problem: &Problem, chance: f64, rng: &mut Rng) -> Vec<Move> {
+        let mut moves = vec![];
+        for (i, line) in lines.iter().enumerate() {
+            if line.n < 2 || rng.f64() > chance {continue};
+            moves.push(Move::replace(i, TrainLine { n: line.n - 1, ..line.clone() }));
+        }
+        moves
+    }
+}
+
+/// Change whether a line runs in a circle or back and forth
+#[derive(Debug, Clone, Copy)]
+pub struct ChangeSchedule;
+impl NeighbourhoodOperator for ChangeSchedule {
+    fn name(&self) -> &str { "change-schedule" }
+    fn moves(&self, lines: &[TrainLine], _problem: &Problem, chance: f64, rng: &mut Rng) -> Vec<Move> {
+        let mut moves = vec![];
+        for (i, line) in lines.iter().enumerate() {
+            if rng.f64() > chance {continue};
+            let ty = match line.ty {
+                ScheduleType::Bidirectional => ScheduleType::Circular,
+                ScheduleType::Circular => ScheduleType::Bidirectional
+            };
+            moves.push(Move::replace(i, TrainLine { ty, ..line.clone() }));
+        }
+        moves
+    }
+}
@@ -0,0 +1,54 @@
+//! Observing the progress of a local search, for progress bars, logging and tests
+
+use std::time::Duration;
+
+use crate::problem::TrainLine;
+
+use super::{MetaheuristicStatus, StopReason};
+
+/// Something that happened during a search
+#[derive(Debug, Clone, PartialEq)]
+pub enum SearchEvent<'a> {
+    /// An iteration finished
+    Iteration {
+        /// The iteration number, counting from 1
+        iteration: usize,
+        current_score: f64,
+        best_score: f64,
+        /// The name of the operator whose move was made this iteration, or `None` if no neighbour was accepted
+        operator: Option<&'a str>,
+        status: MetaheuristicStatus,
+        /// The lines of the current solution
+        solution: &'a [TrainLine],
+        /// The cost of the current solution
+        cost: f64,
+        /// The time since the search started
+        elapsed: Duration
+    },
+    /// The search went stale, so restarted from an earlier good solution
+    Restart {
+        iteration: usize,
+        /// The score of the solution restarted from
+        current_score: f64
+    },
+    /// The search stopped
+    Finished {
+        stop_reason: StopReason,
+        iterations: usize,
+        best_score: f64
+    }
+}
+
+/// Receives events as a search runs
+pub trait Observer: Send {
+    fn observe(&mut self, event: &SearchEvent<'_>);
+}
+/// Ignores every event
+impl Observer for () {
+    fn observe(&mut self, _event: &SearchEvent<'_>) {}
+}
+impl<F: FnMut(&SearchEvent<'_>) + Send> Observer for F {
+    fn observe(&mut self, event: &SearchEvent<'_>) {
+        self(event)
+    }
+}
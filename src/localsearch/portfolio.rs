@@ -0,0 +1,254 @@
+//! Runs several local searches at once, each on its own thread, as islands which
+//! periodically share the best solution any of them has found
+
+use std::{panic, sync::{Condvar, Mutex, MutexGuard, PoisonError}, thread, time::Instant};
+
+use fastrand::Rng;
+use itertools::Itertools;
+
+use crate::problem::Solution;
+
+use super::{observer::{Observer, SearchEvent}, Metaheuristic, MetaheuristicStatus, Running, SolveOutcome, Solver, StopReason, WorkingSolution};
+
+/// A local search which can run as an island of a portfolio
+pub trait Island: Sync {
+    /// Searches, trading solutions with the other islands every `migration.interval` iterations
+    fn run(&self, index: usize, migration: &Migration<'_>) -> IslandStats;
+}
+
+/// Several local searches run at once over the same problem, returning the best solution of any of them
+pub struct Portfolio<'a> {
+    /// The local searches to run, each with a name to report its statistics under.
+    /// Each runs until its own limits are reached.
+    pub islands: Vec<(String, Box<dyn Island + 'a>)>,
+    /// The number of iterations each island runs between taking the best solution found so far,
+    /// if it is better than its own
+    pub migration_interval: usize
+}
+
+/// How one island of a portfolio did
+#[derive(Debug, Clone, PartialEq)]
+pub struct IslandStats {
+    pub name: String,
+    /// The best score the island held, whether it found it or took it from another island
+    pub best_score: f64,
+    pub iterations: usize,
+    /// The number of times the island took a better solution from another island
+    pub migrations: usize,
+    /// The number of migrations at which the island held the best solution of the portfolio
+    pub leading: usize,
+    pub stop_reason: StopReason
+}
+
+/// The result of a portfolio
+#[derive(Debug, Clone, PartialEq)]
+pub struct PortfolioOutcome {
+    /// The best solution of any island, why the portfolio stopped, and the number of migrations
+    pub outcome: SolveOutcome,
+    /// How each island did, in order
+    pub islands: Vec<IslandStats>
+}
+
+/// What the islands share, updated at each migration
+struct Shared<'o> {
+    /// The best solution so far and its score, with the island which found it
+    best: Option<(WorkingSolution, f64, usize)>,
+    /// The hash of each island's solution, and whether it is still searching
+    islands: Vec<(u64, bool)>,
+    /// The number of migrations so far
+    migrations: usize,
+    /// The number of islands which have not stopped, which every island waits for
+    running: usize,
+    /// The number of islands waiting for the others
+    waiting: usize,
+    /// The number of times every island has finished waiting
+    generation: usize,
+    observer: &'o mut dyn Observer
+}
+
+/// Lets the islands of a portfolio trade solutions
+pub struct Migration<'o> {
+    shared: Mutex<Shared<'o>>,
+    /// Wakes the islands waiting for the others
+    arrived: Condvar,
+    /// The number of iterations each island runs between migrations
+    interval: usize,
+    start: Instant
+}
+
+impl<'a> Portfolio<'a> {
+    /// Solve the problem, returning the best solution found once every island has stopped
+    #[allow(unused)]
+    pub fn solve(&self) -> PortfolioOutcome {
+        self.solve_observed(&mut ())
+    }
+    /// Solve the problem, telling `observer` about each migration.
+    /// The status of each iteration is the number of distinct solutions the islands hold.
+    pub fn solve_observed(&self, observer: &mut dyn Observer) -> PortfolioOutcome {
+        let migration = Migration {
+            shared: Mutex::new(Shared {
+                best: None, islands: vec![(0, true); self.islands.len()], migrations: 0,
+                running: self.islands.len(), waiting: 0, generation: 0, observer
+            }),
+            arrived: Condvar::new(),
+            interval: self.migration_interval.max(1),
+            start: Instant::now()
+        };
+        let islands = thread::scope(|scope| {
+            let handles = self.islands.iter().enumerate()
+                .map(|(i, (_, island))| {
+                    let migration = &migration;
+                    scope.spawn(move || {
+                        let _leave = Leave { migration, index: i };
+                        island.run(i, migration)
+                    })
+                })
+                .collect_vec();
+            // An island panicking is a bug, so the panic is passed on once the other islands have stopped
+            handles.into_iter().map(|h| h.join().unwrap_or_else(|payload| panic::resume_unwind(payload))).collect_vec()
+        });
+        let islands = islands.into_iter().zip(&self.islands)
+            .map(|(stats, (name, _))| IslandStats { name: name.clone(), ..stats })
+            .collect_vec();
+
+        // UNWRAP: only panics if an island panicked
+        let shared = migration.shared.into_inner().unwrap();
+        // Limits on the whole run stop every island, so any island's reason is the portfolio's
+        let stop_reason = islands.iter().map(|s| s.stop_reason)
+            .find(|r| matches!(r, StopReason::TimeLimit | StopReason::Cancelled))
+            .or(islands.iter().map(|s| s.stop_reason).find(|r| *r == StopReason::NoImprovement))
+            .unwrap_or(StopReason::MaxIterations);
+        // UNWRAP: every island publishes a solution at least once
+        let (solution, score, _) = shared.best.unwrap();
+        shared.observer.observe(&SearchEvent::Finished { stop_reason, iterations: shared.migrations, best_score: score });
+        PortfolioOutcome {
+            outcome: SolveOutcome {
+                solution: Solution { built_tracks: solution.built_tracks, train_lines: solution.train_lines, obj_value: score },
+                stop_reason, iterations: shared.migrations
+            },
+            islands
+        }
+    }
+}
+
+impl<M: Metaheuristic> Island for Solver<'_, M> {
+    fn run(&self, index: usize, migration: &Migration<'_>) -> IslandStats {
+        self.in_pool(|| {
+            let mut rng = Rng::with_seed(self.seed);
+            // The metaheuristic, and how long the search has gone without improving, carry on between migrations,
+            // rather than starting again
+            let mut running = Running::new(self.mh_params.clone());
+            let mut solution = WorkingSolution::new(self.problem);
+            let mut score = f64::INFINITY;
+            let mut stats = IslandStats {
+                name: String::new(), best_score: f64::INFINITY, iterations: 0, migrations: 0, leading: 0,
+                stop_reason: StopReason::MaxIterations
+            };
+            let mut active = true;
+            loop {
+                if active {
+                    let iterations = migration.interval.min(self.max_iterations - stats.iterations);
+                    // Each stretch carries on from the best solution of the last
+                    let search = self.search(&mut running, solution, iterations, migration.start, &mut rng, &mut ());
+                    stats.iterations += search.iterations;
+                    (solution, score) = (search.solution, search.score);
+                    stats.best_score = stats.best_score.min(score);
+                    // Reaching the end of a stretch is the only reason to keep searching
+                    if search.stop_reason != StopReason::MaxIterations || stats.iterations >= self.max_iterations {
+                        stats.stop_reason = search.stop_reason;
+                        active = false;
+                    }
+                }
+                migration.publish(index, &solution, score, active);
+                migration.wait();
+                let (best, any_active) = migration.collect(index);
+                migration.wait();
+                if let Some((best, best_score, from)) = best {
+                    if from == index {
+                        stats.leading += 1;
+                    } else if active && best_score < score {
+                        (solution, score) = (best, best_score);
+                        stats.best_score = score;
+                        stats.migrations += 1;
+                    }
+                }
+                if !any_active {return stats};
+            }
+        })
+    }
+}
+
+/// Leaves the migration when an island stops, even by panicking, so that the other islands do not wait for it
+struct Leave<'m, 'o> {
+    migration: &'m Migration<'o>,
+    index: usize
+}
+impl Drop for Leave<'_, '_> {
+    fn drop(&mut self) {
+        self.migration.leave(self.index);
+    }
+}
+
+impl<'o> Migration<'o> {
+    /// Waits until every island which has not stopped is waiting too
+    fn wait(&self) {
+        // UNWRAP: only panics if another island panicked
+        let mut shared = self.shared.lock().unwrap();
+        shared.waiting += 1;
+        if shared.waiting >= shared.running {
+            self.release(&mut shared);
+            return;
+        }
+        let generation = shared.generation;
+        while shared.generation == generation {
+            // UNWRAP: only panics if another island panicked
+            shared = self.arrived.wait(shared).unwrap();
+        }
+    }
+    /// Lets every waiting island carry on
+    fn release(&self, shared: &mut MutexGuard<'_, Shared<'o>>) {
+        shared.waiting = 0;
+        shared.generation += 1;
+        self.arrived.notify_all();
+    }
+    /// Stops waiting for an island, which is no longer searching
+    fn leave(&self, index: usize) {
+        // An island which panicked may have poisoned the lock, but the others still need releasing
+        let mut shared = self.shared.lock().unwrap_or_else(PoisonError::into_inner);
+        shared.islands[index].1 = false;
+        shared.running -= 1;
+        if shared.waiting > 0 && shared.waiting >= shared.running {
+            self.release(&mut shared);
+        }
+    }
+    /// Offers an island's solution as the best so far, and records whether it is still searching
+    fn publish(&self, index: usize, solution: &WorkingSolution, score: f64, active: bool) {
+        // UNWRAP: only panics if another island panicked
+        let mut shared = self.shared.lock().unwrap();
+        shared.islands[index] = (solution.hash, active);
+        // Ties go to the first island, so the best does not depend on which island publishes first
+        let better = shared.best.as_ref().is_none_or(|&(_, best, from)| score < best || (score == best && index < from));
+        if better {
+            shared.best = Some((solution.clone(), score, index));
+        }
+    }
+    /// Once every island has published, the best solution so far and whether any island is still searching.
+    /// The first island also tells the observer about the migration.
+    fn collect(&self, index: usize) -> (Option<(WorkingSolution, f64, usize)>, bool) {
+        // UNWRAP: only panics if another island panicked
+        let mut shared = self.shared.lock().unwrap();
+        let any_active = shared.islands.iter().any(|&(_, active)| active);
+        if index == 0 {
+            shared.migrations += 1;
+            let Shared { best, islands, migrations, observer, .. } = &mut *shared;
+            if let Some((solution, score, _)) = best {
+                observer.observe(&SearchEvent::Iteration {
+                    iteration: *migrations, current_score: *score, best_score: *score, operator: None,
+                    status: MetaheuristicStatus::Diversity(islands.iter().map(|&(hash, _)| hash).unique().count()),
+                    solution: &solution.train_lines, cost: solution.cost, elapsed: self.start.elapsed()
+                });
+            }
+        }
+        (shared.best.clone(), any_active)
+    }
+}
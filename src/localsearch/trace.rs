@@ -0,0 +1,111 @@
+//! Records the trajectory of a search to a file, for plotting
+
+use std::{fs::File, io::{self, BufWriter, Write}};
+
+use serde::Serialize;
+
+use super::observer::{Observer, SearchEvent};
+
+/// The format a trace is written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Csv, JsonLines
+}
+impl TraceFormat {
+    /// Picks the format from a file's extension: CSV for `.csv`, otherwise JSON lines
+    pub fn from_file_name(file_name: &str) -> Self {
+        if file_name.ends_with(".csv") {TraceFormat::Csv} else {TraceFormat::JsonLines}
+    }
+}
+
+/// The state of the search at one iteration
+#[derive(Serialize, Debug, Clone, PartialEq)]
+struct TraceRow {
+    iteration: usize,
+    current_score: f64,
+    best_score: f64,
+    cost: f64,
+    lines: usize,
+    trains: usize,
+    /// The operator whose move was accepted this iteration, empty if none was
+    operator: String,
+    /// Seconds since the search started
+    elapsed: f64
+}
+
+/// An observer writing every `every`th iteration of a search to a file.
+/// The last iteration is always written.
+pub struct Trace<W: Write = BufWriter<File>> {
+    out: W,
+    format: TraceFormat,
+    every: usize,
+    /// The last iteration seen, if it was not written
+    pending: Option<TraceRow>,
+    /// The first error writing the trace, reported by `finish`
+    error: Option<io::Error>
+}
+impl Trace {
+    /// Creates a trace file, in CSV or JSON lines format depending on its extension
+    pub fn create(file_name: &str, every: usize) -> io::Result<Self> {
+        Trace::new(BufWriter::new(File::create(file_name)?), TraceFormat::from_file_name(file_name), every)
+    }
+}
+impl<W: Write> Trace<W> {
+    /// Writes a trace to `out`, recording every `every`th iteration
+    pub fn new(mut out: W, format: TraceFormat, every: usize) -> io::Result<Self> {
+        if format == TraceFormat::Csv {
+            writeln!(out, "iteration,current_score,best_score,cost,lines,trains,operator,elapsed")?;
+        }
+        Ok(Self { out, format, every: every.max(1), pending: None, error: None })
+    }
+    /// Flushes the trace, returning the first error met while writing it
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+    fn write_row(&mut self, row: &TraceRow) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Csv => writeln!(
+                self.out, "{},{},{},{},{},{},{},{}",
+                row.iteration, row.current_score, row.best_score, row.cost, row.lines, row.trains, row.operator, row.elapsed
+            ),
+            TraceFormat::JsonLines => {
+                serde_json::to_writer(&mut self.out, row)?;
+                writeln!(self.out)
+            }
+        }
+    }
+}
+impl<W: Write + Send> Observer for Trace<W> {
+    fn observe(&mut self, event: &SearchEvent<'_>) {
+        let row = match *event {
+            SearchEvent::Iteration { iteration, current_score, best_score, operator, solution, cost, elapsed, .. } => {
+                let row = TraceRow {
+                    iteration, current_score, best_score, cost,
+                    lines: solution.len(),
+                    trains: solution.iter().map(|l| l.n).sum(),
+                    operator: operator.unwrap_or_default().to_string(),
+                    elapsed: elapsed.as_secs_f64()
+                };
+                if iteration % self.every == 0 {
+                    self.pending = None;
+                    row
+                } else {
+                    self.pending = Some(row);
+                    return;
+                }
+            }
+            SearchEvent::Restart { .. } => return,
+            SearchEvent::Finished { .. } => match self.pending.take() {
+                Some(row) => row,
+                None => return
+            }
+        };
+        if self.error.is_none() {
+            self.error = self.write_row(&row).err();
+        }
+    }
+}
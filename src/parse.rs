@@ -1,17 +1,211 @@
-//! Parses a problem from a file to the internal problem representation
+//! Parses a problem from a file to the internal problem representation,
+//! and reads and writes solutions to it
 
-use std::{fs::{self, File}, io::Write};
+use std::{fmt, fs::{self, File}, io::{self, Write}};
 
-use crate::problem::Problem;
+use ndarray::ArrayD;
+use serde::{Deserialize, Serialize};
 
-/// Reads a problem from a file, in TOML format
-pub fn parse_problem(file_name: &str) -> Problem {
-    let file_contents = fs::read_to_string(file_name).unwrap();
-    toml::from_str(&file_contents).unwrap()
+use crate::{evaluate::evaluate, problem::{EvaluationModel, Problem, ProblemError, Solution, TrainLine}};
+
+/// The version of the solution file format written by `save_solution`.
+/// Version 2 stores the evaluation model the objective value was found with.
+pub const SOLUTION_FORMAT_VERSION: u32 = 2;
+
+/// The relative tolerance when checking stored values against recalculated ones
+const VALUE_TOLERANCE: f64 = 1e-9;
+
+/// Reads a problem from a file, in TOML format.
+/// The problem is not validated: use `Problem::validate` for that.
+pub fn parse_problem(file_name: &str) -> Result<Problem, ProblemError> {
+    let file_contents = fs::read_to_string(file_name)?;
+    Ok(toml::from_str(&file_contents)?)
 }
 
 /// Saves a problem in TOML format to a file
-pub fn save_problem(file_name: &str, problem: &Problem) {
-    let mut file = File::create(file_name).unwrap();
-    write!(file, "{}", toml::to_string(&problem).unwrap()).unwrap();
-}
\ No newline at end of file
+pub fn save_problem(file_name: &str, problem: &Problem) -> Result<(), ProblemError> {
+    let contents = toml::to_string(&problem)?;
+    let mut file = File::create(file_name)?;
+    write!(file, "{contents}")?;
+    Ok(())
+}
+
+/// The format a solution is stored in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Toml, Json
+}
+impl Format {
+    /// Picks the format from a file's extension, defaulting to TOML
+    pub fn from_file_name(file_name: &str) -> Self {
+        if file_name.ends_with(".json") {Format::Json} else {Format::Toml}
+    }
+}
+
+/// A solution as it is stored on disk
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+struct SolutionFile {
+    /// The version of the format, for compatibility
+    version: u32,
+    /// The objective value; checked against the evaluated value when loading, if present
+    obj_value: Option<f64>,
+    /// The cost; checked against the calculated cost when loading, if present
+    cost: Option<f64>,
+    /// The built tracks, as a list of pairs of stations, each listed once
+    built_tracks: Vec<(usize, usize)>,
+    /// A list of train lines descriptions
+    train_lines: Vec<TrainLine>,
+    /// The evaluation model the objective value was found with, if it is not the problem's own.
+    /// Last, as TOML writes tables after values.
+    evaluation: Option<EvaluationModel>
+}
+
+/// An error encountered reading or writing a solution
+#[derive(Debug)]
+pub enum SolutionError {
+    Io(io::Error),
+    TomlDe(toml::de::Error),
+    TomlSer(toml::ser::Error),
+    Json(serde_json::Error),
+    /// The file was written by a newer version of the format
+    UnsupportedVersion(u32),
+    /// A track or line refers to a station not in the problem
+    StationOutOfRange { station: usize, n: usize },
+    /// A line visits no stations
+    EmptyLine { line: usize },
+    /// The stored evaluation model is not valid for the problem
+    InvalidEvaluation(ProblemError),
+    /// The stored objective value disagrees with evaluating the solution
+    ObjectiveMismatch { stored: f64, evaluated: f64 },
+    /// The stored cost disagrees with the cost of the solution
+    CostMismatch { stored: f64, calculated: f64 }
+}
+impl fmt::Display for SolutionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SolutionError::Io(e) => write!(f, "{e}"),
+            SolutionError::TomlDe(e) => write!(f, "invalid TOML: {e}"),
+            SolutionError::TomlSer(e) => write!(f, "could not write TOML: {e}"),
+            SolutionError::Json(e) => write!(f, "invalid JSON: {e}"),
+            SolutionError::UnsupportedVersion(v) => write!(
+                f, "solution format version {v} is newer than the supported version {SOLUTION_FORMAT_VERSION}"
+            ),
+            SolutionError::StationOutOfRange { station, n } => write!(
+                f, "solution refers to station {station}, but the problem has {n} stations"
+            ),
+            SolutionError::EmptyLine { line } => write!(f, "line {line} visits no stations"),
+            SolutionError::InvalidEvaluation(e) => write!(f, "the stored evaluation model is invalid: {e}"),
+            SolutionError::ObjectiveMismatch { stored, evaluated } => write!(
+                f, "stored objective value {stored} does not match evaluated value {evaluated}"
+            ),
+            SolutionError::CostMismatch { stored, calculated } => write!(
+                f, "stored cost {stored} does not match calculated cost {calculated}"
+            )
+        }
+    }
+}
+impl std::error::Error for SolutionError {}
+impl From<io::Error> for SolutionError {
+    fn from(e: io::Error) -> Self { SolutionError::Io(e) }
+}
+impl From<toml::de::Error> for SolutionError {
+    fn from(e: toml::de::Error) -> Self { SolutionError::TomlDe(e) }
+}
+impl From<toml::ser::Error> for SolutionError {
+    fn from(e: toml::ser::Error) -> Self { SolutionError::TomlSer(e) }
+}
+impl From<serde_json::Error> for SolutionError {
+    fn from(e: serde_json::Error) -> Self { SolutionError::Json(e) }
+}
+
+/// Whether two values are equal, up to floating point error
+fn approx_eq(a: f64, b: f64) -> bool {
+    a == b || (a - b).abs() <= VALUE_TOLERANCE * a.abs().max(b.abs())
+}
+
+/// Converts a solution to a string in the given format
+pub fn solution_to_string(solution: &Solution, problem: &Problem, format: Format) -> Result<String, SolutionError> {
+    let n = solution.built_tracks.shape()[0];
+    let built_tracks = (0..n).flat_map(|i| (i+1..n).map(move |j| (i, j)))
+        .filter(|&(i, j)| solution.built_tracks[[i, j]])
+        .collect();
+    let file = SolutionFile {
+        version: SOLUTION_FORMAT_VERSION,
+        obj_value: Some(solution.obj_value),
+        cost: Some(solution.cost(problem)),
+        built_tracks,
+        train_lines: solution.train_lines.clone(),
+        evaluation: Some(problem.evaluation.clone())
+    };
+    Ok(match format {
+        Format::Toml => toml::to_string(&file)?,
+        Format::Json => serde_json::to_string_pretty(&file)?
+    })
+}
+
+/// Reads a solution to `problem` from a string in the given format,
+/// checking any stored objective value and cost are correct.
+/// The objective value is checked with the evaluation model stored with it, if any, and is then evaluated with the problem's.
+pub fn solution_from_str(contents: &str, problem: &Problem, format: Format) -> Result<Solution, SolutionError> {
+    let file: SolutionFile = match format {
+        Format::Toml => toml::from_str(contents)?,
+        Format::Json => serde_json::from_str(contents)?
+    };
+    if file.version > SOLUTION_FORMAT_VERSION {
+        return Err(SolutionError::UnsupportedVersion(file.version));
+    }
+    let stations = file.built_tracks.iter().flat_map(|&(a, b)| [a, b])
+        .chain(file.train_lines.iter().flat_map(|l| l.route.iter().copied()));
+    for station in stations {
+        if station >= problem.n {
+            return Err(SolutionError::StationOutOfRange { station, n: problem.n });
+        }
+    }
+
+    if let Some(line) = file.train_lines.iter().position(|l| l.route.is_empty()) {
+        return Err(SolutionError::EmptyLine { line });
+    }
+
+    let mut built_tracks = ArrayD::<bool>::default(problem.track_costs.shape());
+    for (a, b) in file.built_tracks {
+        built_tracks[[a, b]] = true; built_tracks[[b, a]] = true;
+    }
+    let obj_value = evaluate(problem, &file.train_lines);
+    let solution = Solution { built_tracks, train_lines: file.train_lines, obj_value };
+
+    if let Some(stored) = file.obj_value {
+        let evaluated = match file.evaluation {
+            Some(model) if model != problem.evaluation => {
+                let stored_problem = Problem { evaluation: model, ..problem.clone() };
+                stored_problem.validate().map_err(SolutionError::InvalidEvaluation)?;
+                evaluate(&stored_problem, &solution.train_lines)
+            }
+            _ => obj_value
+        };
+        if !approx_eq(stored, evaluated) {
+            return Err(SolutionError::ObjectiveMismatch { stored, evaluated });
+        }
+    }
+    if let Some(stored) = file.cost {
+        let calculated = solution.cost(problem);
+        if !approx_eq(stored, calculated) {
+            return Err(SolutionError::CostMismatch { stored, calculated });
+        }
+    }
+    Ok(solution)
+}
+
+/// Reads a solution to `problem` from a file, in TOML or JSON format depending on its extension.
+/// Any stored objective value and cost are checked against the problem, and the evaluation model stored with them.
+pub fn parse_solution(file_name: &str, problem: &Problem) -> Result<Solution, SolutionError> {
+    let file_contents = fs::read_to_string(file_name)?;
+    solution_from_str(&file_contents, problem, Format::from_file_name(file_name))
+}
+
+/// Saves a solution to `problem` to a file, in TOML or JSON format depending on its extension
+pub fn save_solution(file_name: &str, solution: &Solution, problem: &Problem) -> Result<(), SolutionError> {
+    let contents = solution_to_string(solution, problem, Format::from_file_name(file_name))?;
+    let mut file = File::create(file_name)?;
+    write!(file, "{contents}")?;
+    Ok(())
+}
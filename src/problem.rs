@@ -1,6 +1,8 @@
 //! This module contains interfaces for the solver: it has the `Problem` struct, which describes a train routing problem,
 //! and the `Solution` struct, which is what the solver returns and represents the optimal solution
 
+use std::{borrow::Cow, fmt, io};
+
 use ndarray::ArrayD;
 use serde::{Deserialize, Serialize};
 
@@ -18,7 +20,54 @@ pub struct Problem {
     /// The price per train
     pub train_price: f64,
     /// The total amount of money that can be allocated
-    pub total_budget: f64
+    pub total_budget: f64,
+    /// A symmetric matrix representing the time to travel between two stations by another mode,
+    /// such as by car or bus, for journeys the train network does not serve
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alternative_times: Option<ArrayD<f64>>,
+    /// How commuters' journeys are modelled when evaluating solutions
+    #[serde(default)]
+    pub evaluation: EvaluationModel
+}
+
+/// Large constant penalty for disconnect between stations, charged per unit of unserved demand by default
+pub const DEFAULT_TRAVEL_TIME: f64 = 1e10;
+
+/// Parameters for how commuters' journeys are modelled when evaluating a solution
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct EvaluationModel {
+    /// The maximum number of times a commuter will change lines on a single journey
+    pub max_transfers: usize,
+    /// A fixed time added to every change of lines, on top of the expected wait for the next train
+    pub transfer_penalty: f64,
+    /// The time taken to walk between platforms when changing lines at each station, if any
+    pub transfer_times: Option<Vec<f64>>,
+    /// How demand between stations the network does not connect is counted
+    pub unserved: UnservedPolicy
+}
+impl Default for EvaluationModel {
+    fn default() -> Self {
+        Self { max_transfers: 2, transfer_penalty: 0.0, transfer_times: None, unserved: UnservedPolicy::default() }
+    }
+}
+
+/// How demand between stations that the network does not connect is counted in the objective
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "policy", rename_all = "snake_case")]
+pub enum UnservedPolicy {
+    /// Unserved demand is a separate term of the objective, charged at `weight` per unit of demand
+    Penalty { weight: f64 },
+    /// Unserved journeys are made by another mode, taking `factor` times the problem's alternative time.
+    /// If the problem has no alternative times, the default penalty is charged instead.
+    Alternative { factor: f64 },
+    /// Any unserved demand makes a solution infinitely bad
+    Hard
+}
+impl Default for UnservedPolicy {
+    fn default() -> Self {
+        UnservedPolicy::Penalty { weight: DEFAULT_TRAVEL_TIME }
+    }
 }
 
 /// Represents which type of line a train follows:
@@ -26,13 +75,13 @@ pub struct Problem {
 /// - `Circular` means it goes to the first station after the last one
 /// 
 /// - `Bidirectional` means it repeats the track, reversed
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum ScheduleType {
     Circular, Bidirectional
 }
 
 /// A train line: its schedule, with how many trains it runs
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct TrainLine {
     /// A list of stations which trains on this line visit
     pub route: Vec<usize>,
@@ -42,6 +91,70 @@ pub struct TrainLine {
     pub n: usize
 }
 
+impl TrainLine {
+    /// The route read in a standard way, the same for every route running the same service.
+    /// A bidirectional route is the same read either way, so it is read from whichever end gives the lesser route.
+    /// A circular route is the same from any station, so it is read from whichever station gives the least route.
+    pub fn canonical_route(&self) -> Cow<'_, [usize]> {
+        match self.ty {
+            ScheduleType::Bidirectional if self.route.iter().rev().lt(&self.route) => {
+                Cow::Owned(self.route.iter().rev().copied().collect())
+            }
+            ScheduleType::Circular => {
+                let rotated = |start: usize| self.route[start..].iter().chain(&self.route[..start]);
+                // Ties go to the first station, so a route already in the standard form is not copied
+                match (0..self.route.len()).min_by(|&a, &b| rotated(a).cmp(rotated(b))) {
+                    Some(start) if start > 0 => Cow::Owned(rotated(start).copied().collect()),
+                    _ => Cow::Borrowed(&self.route)
+                }
+            }
+            _ => Cow::Borrowed(&self.route)
+        }
+    }
+    /// This line with its route read in the standard way
+    pub fn canonical(&self) -> TrainLine {
+        TrainLine { route: self.canonical_route().into_owned(), ty: self.ty, n: self.n }
+    }
+}
+
+/// The lines in a standard form, the same for every set of lines running the same services:
+/// each line is in its canonical form, and the lines are sorted
+pub fn canonical_lines(lines: &[TrainLine]) -> Vec<TrainLine> {
+    let mut lines = lines.iter().map(TrainLine::canonical).collect::<Vec<_>>();
+    lines.sort_unstable();
+    lines
+}
+
+/// Helper iterator to visit all tracks on a single train line
+pub(crate) struct TrainTrackIterator<'a> {
+    train_line: &'a TrainLine,
+    i: usize
+}
+impl<'a> TrainTrackIterator<'a> {
+    /// Create a new track iterator for a specific line
+    pub fn new(train_line: &'a TrainLine) -> Self {
+        Self { train_line, i: 0 }
+    }
+}
+impl<'a> Iterator for TrainTrackIterator<'a> {
+    type Item = (usize, usize); // represents two stations
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.i == self.train_line.route.len() - 1 + match self.train_line.ty {
+            ScheduleType::Bidirectional => 0,
+            ScheduleType::Circular => 1
+        } {
+            return None;
+        }
+        self.i += 1;
+        if self.train_line.ty == ScheduleType::Circular && self.i == self.train_line.route.len() {
+            // UNWRAP: a train line will always have a station
+            return Some((self.train_line.route[0], *self.train_line.route.last().unwrap()));
+        }
+        Some((self.train_line.route[self.i-1], self.train_line.route[self.i]))
+    }
+}
+
 /// The solver's optimal solution to the problem
 #[derive(Debug, Clone, PartialEq)]
 pub struct Solution {
@@ -63,4 +176,233 @@ impl Solution {
     pub fn check_feasibility(&self, problem: &Problem) -> bool {
         self.cost(problem) <= problem.total_budget
     }
-}
\ No newline at end of file
+    /// Checks the solution can be used for the problem: it must have a line,
+    /// every line must visit at least two of the problem's stations, each only once, and run at least one train,
+    /// every track its lines use must be built, and it must be within budget
+    pub fn validate(&self, problem: &Problem) -> Result<(), InvalidSolution> {
+        let shape = self.built_tracks.shape();
+        if shape != [problem.n, problem.n] {
+            return Err(InvalidSolution::TracksShape { shape: shape.to_vec(), n: problem.n });
+        }
+        if self.train_lines.is_empty() {
+            return Err(InvalidSolution::NoLines);
+        }
+        for (i, line) in self.train_lines.iter().enumerate() {
+            if let Some(&station) = line.route.iter().find(|&&s| s >= problem.n) {
+                return Err(InvalidSolution::StationOutOfRange { line: i, station, n: problem.n });
+            }
+            if line.route.len() < 2 {
+                return Err(InvalidSolution::ShortLine { line: i });
+            }
+            if let Some(&station) = line.route.iter().enumerate().find_map(|(j, s)| line.route[..j].contains(s).then_some(s)) {
+                return Err(InvalidSolution::RepeatedStation { line: i, station });
+            }
+            if line.n == 0 {
+                return Err(InvalidSolution::NoTrains { line: i });
+            }
+            if let Some((a, b)) = TrainTrackIterator::new(line).find(|&(a, b)| !self.built_tracks[[a, b]]) {
+                return Err(InvalidSolution::TrackNotBuilt { line: i, a, b });
+            }
+        }
+        if !self.check_feasibility(problem) {
+            return Err(InvalidSolution::OverBudget { cost: self.cost(problem), budget: problem.total_budget });
+        }
+        Ok(())
+    }
+}
+/// Why a solution cannot be used for a problem
+#[derive(Debug, Clone, PartialEq)]
+pub enum InvalidSolution {
+    /// The built tracks are not an `n` by `n` matrix
+    TracksShape { shape: Vec<usize>, n: usize },
+    /// The solution has no lines
+    NoLines,
+    /// A line visits a station not in the problem
+    StationOutOfRange { line: usize, station: usize, n: usize },
+    /// A line visits fewer than two stations
+    ShortLine { line: usize },
+    /// A line visits a station more than once
+    RepeatedStation { line: usize, station: usize },
+    /// A line runs no trains
+    NoTrains { line: usize },
+    /// A line uses a track which is not built
+    TrackNotBuilt { line: usize, a: usize, b: usize },
+    /// The solution costs more than the budget
+    OverBudget { cost: f64, budget: f64 }
+}
+impl fmt::Display for InvalidSolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidSolution::TracksShape { shape, n } => write!(f, "built tracks have shape {shape:?}, but n is {n}"),
+            InvalidSolution::NoLines => write!(f, "solution has no lines"),
+            InvalidSolution::StationOutOfRange { line, station, n } => write!(
+                f, "line {line} visits station {station}, but the problem only has {n} stations"
+            ),
+            InvalidSolution::ShortLine { line } => write!(f, "line {line} visits fewer than two stations"),
+            InvalidSolution::RepeatedStation { line, station } => write!(f, "line {line} visits station {station} more than once"),
+            InvalidSolution::NoTrains { line } => write!(f, "line {line} runs no trains"),
+            InvalidSolution::TrackNotBuilt { line, a, b } => write!(
+                f, "line {line} uses the track between {a} and {b}, which is not built"
+            ),
+            InvalidSolution::OverBudget { cost, budget } => write!(f, "solution costs {cost}, which is over the budget of {budget}")
+        }
+    }
+}
+impl std::error::Error for InvalidSolution {}
+/// A single way in which a problem is malformed
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    /// There are fewer than two stations, so no line can run
+    TooFewStations { n: usize },
+    /// A matrix is not `n` by `n`
+    DimensionMismatch { matrix: &'static str, shape: Vec<usize>, n: usize },
+    /// An entry is NaN
+    NotANumber { matrix: &'static str, i: usize, j: usize },
+    /// An entry is negative
+    Negative { matrix: &'static str, i: usize, j: usize, value: f64 },
+    /// An entry on the diagonal is not zero
+    NonZeroDiagonal { matrix: &'static str, i: usize, value: f64 },
+    /// The entries either side of the diagonal differ
+    NotSymmetric { matrix: &'static str, i: usize, j: usize },
+    /// A scalar field is negative or NaN
+    InvalidValue { field: &'static str, value: f64 },
+    /// The transfer times do not list one time per station
+    TransferTimesLength { len: usize, n: usize },
+    /// A station's transfer time is negative or NaN
+    InvalidTransferTime { station: usize, value: f64 },
+    /// The unserved-demand policy needs alternative times, but the problem has none
+    MissingAlternativeTimes
+}
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::TooFewStations { n } => write!(f, "there must be at least two stations, but n is {n}"),
+            Violation::DimensionMismatch { matrix, shape, n } => write!(f, "{matrix} has shape {shape:?}, but n is {n}"),
+            Violation::NotANumber { matrix, i, j } => write!(f, "{matrix}[{i}, {j}] is NaN"),
+            Violation::Negative { matrix, i, j, value } => write!(f, "{matrix}[{i}, {j}] is negative ({value})"),
+            Violation::NonZeroDiagonal { matrix, i, value } => write!(f, "{matrix}[{i}, {i}] is not zero ({value})"),
+            Violation::NotSymmetric { matrix, i, j } => write!(f, "{matrix}[{i}, {j}] differs from {matrix}[{j}, {i}]"),
+            Violation::InvalidValue { field, value } => write!(f, "{field} must be a non-negative number, but is {value}"),
+            Violation::TransferTimesLength { len, n } => write!(f, "there are {len} transfer times, but n is {n}"),
+            Violation::InvalidTransferTime { station, value } => write!(
+                f, "the transfer time at station {station} must be a non-negative number, but is {value}"
+            ),
+            Violation::MissingAlternativeTimes => write!(
+                f, "the unserved-demand policy uses alternative times, but alternative_times is not given"
+            )
+        }
+    }
+}
+
+/// An error encountered reading, writing or validating a problem
+#[derive(Debug)]
+pub enum ProblemError {
+    Io(io::Error),
+    TomlDe(toml::de::Error),
+    TomlSer(toml::ser::Error),
+    /// The problem is malformed, for every listed reason
+    Invalid(Vec<Violation>)
+}
+impl fmt::Display for ProblemError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProblemError::Io(e) => write!(f, "{e}"),
+            ProblemError::TomlDe(e) => write!(f, "invalid TOML: {e}"),
+            ProblemError::TomlSer(e) => write!(f, "could not write TOML: {e}"),
+            ProblemError::Invalid(violations) => {
+                write!(f, "invalid problem:")?;
+                for v in violations {
+                    write!(f, "\n  {v}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+impl std::error::Error for ProblemError {}
+impl From<io::Error> for ProblemError {
+    fn from(e: io::Error) -> Self { ProblemError::Io(e) }
+}
+impl From<toml::de::Error> for ProblemError {
+    fn from(e: toml::de::Error) -> Self { ProblemError::TomlDe(e) }
+}
+impl From<toml::ser::Error> for ProblemError {
+    fn from(e: toml::ser::Error) -> Self { ProblemError::TomlSer(e) }
+}
+
+impl Problem {
+    /// Checks the problem is well formed: there must be at least two stations, every matrix must be `n` by `n`, symmetric,
+    /// non-negative with a zero diagonal, the price, budget and transfer penalty must be non-negative,
+    /// there must be a non-negative transfer time for each station, if any are given,
+    /// and the unserved-demand policy must have what it needs.
+    /// Every violation found is reported.
+    pub fn validate(&self) -> Result<(), ProblemError> {
+        let mut violations = vec![];
+        if self.n < 2 {
+            violations.push(Violation::TooFewStations { n: self.n });
+        }
+        let mut scalars = vec![
+            ("train_price", self.train_price),
+            ("total_budget", self.total_budget),
+            ("transfer_penalty", self.evaluation.transfer_penalty)
+        ];
+        match self.evaluation.unserved {
+            UnservedPolicy::Penalty { weight } => scalars.push(("unserved.weight", weight)),
+            UnservedPolicy::Alternative { factor } => {
+                scalars.push(("unserved.factor", factor));
+                if self.alternative_times.is_none() {
+                    violations.push(Violation::MissingAlternativeTimes);
+                }
+            }
+            UnservedPolicy::Hard => {}
+        }
+        for (field, value) in scalars {
+            if value.is_nan() || value < 0.0 {
+                violations.push(Violation::InvalidValue { field, value });
+            }
+        }
+        if let Some(transfer_times) = &self.evaluation.transfer_times {
+            if transfer_times.len() != self.n {
+                violations.push(Violation::TransferTimesLength { len: transfer_times.len(), n: self.n });
+            }
+            for (station, &value) in transfer_times.iter().enumerate() {
+                if value.is_nan() || value < 0.0 {
+                    violations.push(Violation::InvalidTransferTime { station, value });
+                }
+            }
+        }
+        let mut matrices = vec![
+            ("track_costs", &self.track_costs),
+            ("track_times", &self.track_times),
+            ("travel_frequencies", &self.travel_frequencies)
+        ];
+        if let Some(alternative_times) = &self.alternative_times {
+            matrices.push(("alternative_times", alternative_times));
+        }
+        for (matrix, m) in matrices {
+            if m.shape() != [self.n, self.n] {
+                violations.push(Violation::DimensionMismatch { matrix, shape: m.shape().to_vec(), n: self.n });
+                continue;
+            }
+            for i in 0..self.n {
+                for j in 0..self.n {
+                    let value = m[[i, j]];
+                    if value.is_nan() {
+                        violations.push(Violation::NotANumber { matrix, i, j });
+                        continue;
+                    }
+                    if value < 0.0 {
+                        violations.push(Violation::Negative { matrix, i, j, value });
+                    }
+                    if i == j && value != 0.0 {
+                        violations.push(Violation::NonZeroDiagonal { matrix, i, value });
+                    }
+                    if i < j && !m[[j, i]].is_nan() && value != m[[j, i]] {
+                        violations.push(Violation::NotSymmetric { matrix, i, j });
+                    }
+                }
+            }
+        }
+        if violations.is_empty() {Ok(())} else {Err(ProblemError::Invalid(violations))}
+    }
+}
@@ -1,17 +1,55 @@
-use std::fs;
+use std::{collections::HashSet, fs, panic::{self, AssertUnwindSafe}, sync::{atomic::AtomicBool, Arc}, thread, time::Duration};
 
-use ndarray::{ArrayD, IxDyn};
+use clap::Parser;
+use fastrand::Rng;
+use itertools::Itertools;
+use ndarray::{array, ArrayD, IxDyn};
 
-use crate::{baseline::big_loop, gen_random_problem, parse::{parse_problem, save_problem}, problem::{ScheduleType, Solution, TrainLine}};
+use crate::{baseline::big_loop, cli::{run, Algorithm, Cli, SolveConfig}, evaluate::{evaluate, evaluate_detailed, evaluate_detailed_with, evaluate_parallel, evaluate_with, EvaluationState, Execution}, generate::gen_random_problem, localsearch::{alns::{default_destroy_operators, default_repair_operators, Alns, AlnsParams}, genetic::GeneticSolver, multistart::{IlsAcceptance, IteratedParams, MultiStart, ScoreSummary}, metaheuristic::{Calibration, GreatDeluge, GreatDelugeParams, LateAcceptance, LateAcceptanceParams, Reheat, SimAnneal, SimAnnealParams, TabuParams, TabuSearch}, observer::SearchEvent, portfolio::{Island, IslandStats, Migration, Portfolio}, trace::{Trace, TraceFormat}, neighbourhood::{default_operators, AddTrain, Move, NeighbourhoodOperator, WeightedOperator}, solution_hash, Changes, Metaheuristic, MetaheuristicStatus, Solver, StopReason}, parse::{parse_problem, parse_solution, save_problem, save_solution, solution_from_str, solution_to_string, Format, SolutionError}, problem::{canonical_lines, EvaluationModel, InvalidSolution, Problem, ProblemError, ScheduleType, Solution, TrainLine, TrainTrackIterator, UnservedPolicy, Violation, DEFAULT_TRAVEL_TIME}};
 
+/// A solver for tests, searching for 30 iterations on one thread with the default operators.
+/// Tests override only the settings they exercise.
+fn test_solver<M: Metaheuristic>(problem: &Problem, mh_params: M::Params) -> Solver<'_, M> {
+    Solver {
+        problem, max_iterations: 30, neighbour_chance: 0.8, seed: 0,
+        time_limit: None, stall_limit: None, cancel: None,
+        evaluation_threads: 1, neighbour_threads: 1, verify_delta: false, operators: default_operators(),
+        mh_params
+    }
+}
+
+/// Simulated annealing cooling by `temp_scale` each iteration, without calibration or reheating
+fn sa_params(initial_temp: f64, temp_scale: f64) -> SimAnnealParams {
+    SimAnnealParams { initial_temp, temp_scale, calibration: None, reheat: None }
+}
+
+/// A test solver making a random walk: a hot, constant temperature accepts almost every neighbour
+fn random_walk_solver(problem: &Problem) -> Solver<'_, SimAnneal> {
+    test_solver(problem, sa_params(1e6, 1.0))
+}
+
+/// The tracks these lines run along, each from the lower numbered station
+fn line_tracks(lines: &[TrainLine]) -> HashSet<(usize, usize)> {
+    lines.iter()
+        .flat_map(|l| TrainTrackIterator::new(l).map(|(a, b)| (a.min(b), a.max(b))))
+        .collect()
+}
+
+/// The tracks a solution has built, each from the lower numbered station
+fn built_tracks(solution: &Solution) -> HashSet<(usize, usize)> {
+    let n = solution.built_tracks.shape()[0];
+    (0..n).flat_map(|i| (i+1..n).map(move |j| (i, j)))
+        .filter(|&(i, j)| solution.built_tracks[[i, j]])
+        .collect()
+}
 
 /// Tests saving and loading capabilities, ensuring that
 /// problem data is consistently (de)serialised.
 #[test]
 fn test_problem_serde() {
-    let problem = gen_random_problem(10, 1.0, 1.0);
-    save_problem("__test.toml", &problem);
-    let problem2 = parse_problem("__test.toml");
+    let problem = gen_random_problem(10, 1.0, 1.0, &mut Rng::new());
+    save_problem("__test.toml", &problem).unwrap();
+    let problem2 = parse_problem("__test.toml").unwrap();
     assert_eq!(problem, problem2, "Ensure problem data (de)serialises consistently");
     fs::remove_file("__test.toml").unwrap();
 }
@@ -19,7 +57,7 @@ fn test_problem_serde() {
 /// Ensures cost is calculated correctly, given a solution's description
 #[test]
 fn test_solution_cost() {
-    let problem = parse_problem("test_problem.toml");
+    let problem = parse_problem("test_problem.toml").unwrap();
     let solution = Solution {
         built_tracks: ArrayD::from_shape_vec(IxDyn(&[3, 3]), vec![
             false, true, true,
@@ -38,7 +76,7 @@ fn test_solution_cost() {
 /// Ensures big loop baseline solution is constructed correctly
 #[test]
 fn test_big_loop() {
-    let problem = parse_problem("test_problem.toml");
+    let problem = parse_problem("test_problem.toml").unwrap();
     let ref_sol1 = Solution {
         built_tracks: ArrayD::from_shape_vec(IxDyn(&[3, 3]), vec![
             false, true, false,
@@ -62,4 +100,796 @@ fn test_big_loop() {
     };
     let sol2 = big_loop(&problem, ScheduleType::Circular);
     assert_eq!(sol2, ref_sol2, "Ensure big loop is constructed correctly (circular)");
-}
\ No newline at end of file
+}
+/// Ensures solutions round-trip through both file formats,
+/// and that tampered objective values are caught on loading
+#[test]
+fn test_solution_serde() {
+    let problem = parse_problem("test_problem.toml").unwrap();
+    let solution = big_loop(&problem, ScheduleType::Circular);
+    for file_name in ["__test_solution.toml", "__test_solution.json"] {
+        save_solution(file_name, &solution, &problem).unwrap();
+        let solution2 = parse_solution(file_name, &problem).unwrap();
+        assert_eq!(solution, solution2, "Ensure solution data (de)serialises consistently");
+        fs::remove_file(file_name).unwrap();
+    }
+
+    let contents = solution_to_string(&solution, &problem, Format::Toml).unwrap()
+        .replace("obj_value = 30.0", "obj_value = 29.0");
+    assert!(matches!(
+        solution_from_str(&contents, &problem, Format::Toml),
+        Err(SolutionError::ObjectiveMismatch { .. })
+    ), "Ensure an incorrect objective value is rejected");
+}
+
+/// Ensures generated problems are valid, and that validation
+/// reports every violation in a malformed problem
+#[test]
+fn test_problem_validate() {
+    gen_random_problem(10, 1.0, 1.0, &mut Rng::new()).validate().expect("Ensure generated problems are valid");
+    for file_name in ["test_problem.toml", "medium_random_problem.toml", "semi_large_random_problem.toml", "semi_large_random_problem_location.toml", "large_random_problem.toml"] {
+        parse_problem(file_name).unwrap().validate().unwrap_or_else(|e| panic!("Ensure {file_name} is valid: {e}"));
+    }
+
+    let mut problem = parse_problem("test_problem.toml").unwrap();
+    problem.track_costs[[0, 1]] = -1.0;
+    problem.track_times[[2, 2]] = f64::NAN;
+    problem.travel_frequencies = ArrayD::zeros(IxDyn(&[2, 2]));
+    let Err(ProblemError::Invalid(violations)) = problem.validate() else {
+        panic!("Ensure a malformed problem is rejected");
+    };
+    assert_eq!(violations, vec![
+        Violation::Negative { matrix: "track_costs", i: 0, j: 1, value: -1.0 },
+        Violation::NotSymmetric { matrix: "track_costs", i: 0, j: 1 },
+        Violation::NotANumber { matrix: "track_times", i: 2, j: 2 },
+        Violation::DimensionMismatch { matrix: "travel_frequencies", shape: vec![2, 2], n: 3 },
+    ], "Ensure every violation is reported");
+
+    let mut problem = gen_random_problem(1, 1.0, 1.0, &mut Rng::new());
+    assert!(matches!(problem.validate(), Err(ProblemError::Invalid(v)) if v == [Violation::TooFewStations { n: 1 }]), "Ensure a single station is rejected");
+    problem.n = 0;
+    assert!(problem.validate().is_err(), "Ensure a problem without stations is rejected");
+    let cli = Cli::try_parse_from(["train-routing", "generate", "-s", "0", "-o", "__test_empty.toml"]).unwrap();
+    assert!(run(cli).is_err(), "Ensure problems without stations are not generated");
+    assert!(fs::metadata("__test_empty.toml").is_err(), "Ensure nothing is written");
+}
+
+/// Ensures the evaluation model's transfer limit and penalties
+/// are applied to journeys which change lines
+#[test]
+fn test_evaluation_model() {
+    let problem = parse_problem("test_problem.toml").unwrap();
+    let train_lines = vec![
+        TrainLine { route: vec![0, 1], ty: ScheduleType::Bidirectional, n: 1 },
+        TrainLine { route: vec![1, 2], ty: ScheduleType::Bidirectional, n: 1 },
+    ];
+    // Riding 0-1 and 1-2 directly, and 0-2 via 1, waiting for the second line
+    assert_eq!(evaluate(&problem, &train_lines), 5.0*3.0 + 2.0*4.0 + 1.0*(3.0 + 2.0 + 4.0));
+
+    let model = EvaluationModel { transfer_penalty: 10.0, transfer_times: Some(vec![0.0, 0.5, 0.0]), ..Default::default() };
+    assert_eq!(
+        evaluate_with(&problem, &train_lines, &model), 5.0*3.0 + 2.0*4.0 + 1.0*(3.0 + 10.5 + 2.0 + 4.0),
+        "Ensure transfer penalties are charged"
+    );
+
+    let model = EvaluationModel { max_transfers: 0, ..Default::default() };
+    assert!(evaluate_with(&problem, &train_lines, &model) >= 1e10, "Ensure the transfer limit is respected");
+}
+
+/// Ensures the transfer limit and penalties apply to the whole of a journey through a lower numbered station,
+/// not just the part before that station's own journey onwards
+#[test]
+fn test_transfer_limit_via_lower_station() {
+    let problem = gen_random_problem(5, 1.0, 30.0, &mut Rng::with_seed(5));
+    let t = |a: usize, b: usize| problem.track_times[[a, b]];
+    // From 0, station 4 is one change away; from 3, it is two, through 0
+    let train_lines = vec![
+        TrainLine { route: vec![3, 0], ty: ScheduleType::Bidirectional, n: 1 },
+        TrainLine { route: vec![0, 1], ty: ScheduleType::Bidirectional, n: 1 },
+        TrainLine { route: vec![1, 4], ty: ScheduleType::Bidirectional, n: 1 },
+    ];
+
+    let model = EvaluationModel { max_transfers: 1, ..Default::default() };
+    let report = evaluate_detailed_with(&problem, &train_lines, &model);
+    assert!(!report.unreachable.contains(&(0, 4)), "Ensure 4 is reachable from 0 with one change");
+    assert!(report.unreachable.contains(&(3, 4)), "Ensure 4 is not reachable from 3 with one change");
+
+    let model = EvaluationModel { max_transfers: 2, transfer_penalty: 10.0, ..Default::default() };
+    let report = evaluate_detailed_with(&problem, &train_lines, &model);
+    // Riding each line, waiting half its round trip for the second and third, with a penalty for each change
+    let expected = t(3, 0) + 10.0 + t(0, 1)/2.0 + t(0, 1) + 10.0 + t(1, 4)/2.0 + t(1, 4);
+    assert!((report.travel_times[[3, 4]] - expected).abs() < 1e-9, "Ensure both changes are charged, taking {expected}, not {}", report.travel_times[[3, 4]]);
+    let journey = report.journeys.iter().find(|j| j.from == 3 && j.to == 4).unwrap();
+    assert_eq!(journey.transfers, vec![0, 1], "Ensure the journey changes at 0 and 1");
+}
+
+/// Ensures detailed evaluation reconstructs journeys consistent with the travel times
+#[test]
+fn test_evaluate_detailed() {
+    let problem = parse_problem("test_problem.toml").unwrap();
+    let train_lines = vec![
+        TrainLine { route: vec![0, 1], ty: ScheduleType::Bidirectional, n: 1 },
+        TrainLine { route: vec![1, 2], ty: ScheduleType::Bidirectional, n: 1 },
+    ];
+    let report = evaluate_detailed(&problem, &train_lines);
+    assert_eq!(report.obj_value, evaluate(&problem, &train_lines), "Ensure the objective matches `evaluate`");
+    assert!(report.unreachable.is_empty());
+    let journey = report.journeys.iter().find(|j| j.from == 0 && j.to == 2).unwrap();
+    assert_eq!(journey.transfers, vec![1], "Ensure the transfer is found");
+    assert_eq!((journey.wait_time, journey.ride_time), (2.0, 7.0), "Ensure time is split between waiting and riding");
+
+    let problem = parse_problem("medium_random_problem.toml").unwrap();
+    let train_lines = vec![
+        TrainLine { route: vec![0, 3, 5, 7, 9], ty: ScheduleType::Bidirectional, n: 2 },
+        TrainLine { route: vec![1, 3, 4, 9, 12], ty: ScheduleType::Circular, n: 1 },
+        TrainLine { route: vec![2, 4, 6, 8], ty: ScheduleType::Bidirectional, n: 1 },
+    ];
+    let report = evaluate_detailed(&problem, &train_lines);
+    assert_eq!(report.obj_value, evaluate(&problem, &train_lines), "Ensure the objective matches `evaluate`");
+    for journey in &report.journeys {
+        assert!(
+            (journey.time - report.travel_times[[journey.from, journey.to]]).abs() < 1e-9,
+            "Ensure journey times match travel times"
+        );
+        for (a, b) in journey.legs.iter().tuple_windows() {
+            assert_eq!(a.to, b.from, "Ensure legs are contiguous");
+        }
+    }
+    assert!(report.unreachable.contains(&(10, 11)), "Ensure stations with no lines are unreachable");
+}
+
+/// Ensures demand between disconnected stations is counted according to the unserved-demand policy
+#[test]
+fn test_unserved_policy() {
+    let mut problem = parse_problem("test_problem.toml").unwrap();
+    let train_lines = vec![TrainLine { route: vec![0, 1], ty: ScheduleType::Bidirectional, n: 1 }];
+
+    let report = evaluate_detailed(&problem, &train_lines);
+    assert_eq!((report.travel_time, report.unserved_demand), (5.0*3.0, 1.0 + 2.0), "Ensure unserved demand is reported separately");
+    assert_eq!(report.unreachable, vec![(0, 2), (1, 2)]);
+    assert_eq!(report.obj_value, 5.0*3.0 + 3.0*DEFAULT_TRAVEL_TIME, "Ensure unserved demand is penalised by default");
+
+    problem.evaluation.unserved = UnservedPolicy::Hard;
+    assert_eq!(evaluate(&problem, &train_lines), f64::INFINITY, "Ensure unserved demand can be a hard constraint");
+
+    problem.evaluation.unserved = UnservedPolicy::Alternative { factor: 2.0 };
+    assert!(problem.validate().is_err(), "Ensure alternative times are required");
+    problem.alternative_times = Some(problem.track_times.clone() * 3.0);
+    problem.validate().unwrap();
+    assert_eq!(evaluate(&problem, &train_lines), 5.0*3.0 + 1.0*2.0*6.0 + 2.0*2.0*12.0, "Ensure alternative times are charged");
+}
+
+/// Ensures incremental evaluation gives identical results to full evaluation
+/// as lines are changed, added and removed
+#[test]
+fn test_delta_evaluation() {
+    let problem = parse_problem("medium_random_problem.toml").unwrap();
+    let mut train_lines = vec![
+        TrainLine { route: vec![0, 3, 5, 7, 9], ty: ScheduleType::Bidirectional, n: 2 },
+        TrainLine { route: vec![1, 3, 4, 9, 12], ty: ScheduleType::Circular, n: 1 },
+        TrainLine { route: vec![2, 4, 6, 8], ty: ScheduleType::Bidirectional, n: 1 },
+    ];
+    let mut state = EvaluationState::new(&problem, &train_lines, Execution::Serial);
+    let changes: [fn(&mut Vec<TrainLine>); 5] = [
+        |lines| lines[2].n += 1,
+        |lines| lines[1].route.insert(2, 15),
+        |lines| lines.push(TrainLine { route: vec![16, 17, 18], ty: ScheduleType::Bidirectional, n: 1 }),
+        |lines| { lines.swap_remove(0); },
+        |lines| lines[0].ty = ScheduleType::Bidirectional,
+    ];
+    for change in changes {
+        change(&mut train_lines);
+        state = state.delta(&problem, &train_lines, Execution::Serial);
+        assert_eq!(state.obj_value(), evaluate(&problem, &train_lines), "Ensure incremental evaluation matches `evaluate`");
+        assert_eq!(state, EvaluationState::new(&problem, &train_lines, Execution::Serial), "Ensure incremental evaluation matches full evaluation");
+    }
+}
+
+/// Ensures the evaluations a search keeps from scoring neighbours stay identical to full evaluation
+/// through a random walk over every operator
+#[test]
+fn test_delta_random_walk() {
+    let problem = gen_random_problem(10, 1.0, 30.0, &mut Rng::with_seed(5));
+    // `verify_delta` checks every neighbour scored along the walk
+    let solver = Solver { max_iterations: 200, seed: 6, verify_delta: true, ..random_walk_solver(&problem) };
+    let mut walked = 0;
+    solver.solve_observed(&mut |event: &SearchEvent<'_>| {
+        if let SearchEvent::Iteration { current_score, solution, .. } = *event {
+            assert_eq!(current_score, evaluate(&problem, solution), "Ensure the current score matches `evaluate`");
+            walked += 1;
+        }
+    });
+    assert_eq!(walked, 200, "Ensure every iteration is checked");
+}
+
+/// Ensures journeys can pass through stations numbered below where they start
+#[test]
+fn test_ride_through_lower_stations() {
+    let problem = gen_random_problem(6, 1.0, 30.0, &mut Rng::with_seed(5));
+    // From 2, station 5 can only be reached through 1 or 0
+    let train_lines = vec![TrainLine { route: vec![5, 1, 2, 3, 4, 0], ty: ScheduleType::Bidirectional, n: 1 }];
+    let report = evaluate_detailed(&problem, &train_lines);
+    assert!(report.unreachable.is_empty(), "Ensure every station is reachable, not {:?}", report.unreachable);
+}
+
+/// Ensures each search finds the quickest journeys, riding through stations below its source
+/// and stations it has already reached. Searches used to do neither, so these journeys were slower or missing.
+#[test]
+fn test_search_semantics() {
+    let mut problem = gen_random_problem(4, 1.0, 30.0, &mut Rng::with_seed(5));
+    problem.track_times = array![
+        [0.0, 1.0, 10.0, 1.5],
+        [1.0, 0.0, 1.0, 1.0],
+        [10.0, 1.0, 0.0, 10.0],
+        [1.5, 1.0, 10.0, 0.0]
+    ].into_dyn();
+
+    // Station 1 is reached from 0 by the short line first, but riding on through it is still quicker
+    // than changing there, which waits half the long line's round trip: 1 + 1.75 + 1 = 3.75
+    let train_lines = vec![
+        TrainLine { route: vec![0, 3, 1, 2], ty: ScheduleType::Bidirectional, n: 1 },
+        TrainLine { route: vec![0, 1], ty: ScheduleType::Bidirectional, n: 1 },
+    ];
+    let report = evaluate_detailed(&problem, &train_lines);
+    assert_eq!(report.travel_times[[0, 2]], 1.5 + 1.0 + 1.0, "Ensure journeys ride on through stations already reached");
+    let journey = report.journeys.iter().find(|j| j.from == 0 && j.to == 2).unwrap();
+    assert!(journey.transfers.is_empty(), "Ensure the journey stays on one line, not changing at {:?}", journey.transfers);
+
+    // Leaving 2 either way passes through a lower station first; this used to leave 3 unreachable from 2
+    let train_lines = vec![TrainLine { route: vec![0, 2, 1, 3], ty: ScheduleType::Bidirectional, n: 1 }];
+    let report = evaluate_detailed(&problem, &train_lines);
+    assert_eq!(report.travel_times[[2, 3]], 1.0 + 1.0, "Ensure journeys ride through stations below where they start");
+    assert!(report.unreachable.is_empty(), "Ensure every station is reachable, not {:?}", report.unreachable);
+}
+
+/// Ensures parallel evaluation gives identical results to serial evaluation
+#[test]
+fn test_parallel_evaluation() {
+    let problem = parse_problem("semi_large_random_problem.toml").unwrap();
+    let train_lines = vec![
+        TrainLine { route: (0..20).collect(), ty: ScheduleType::Bidirectional, n: 2 },
+        TrainLine { route: (10..40).step_by(3).collect(), ty: ScheduleType::Circular, n: 1 },
+        TrainLine { route: vec![39, 5, 24, 17, 30], ty: ScheduleType::Bidirectional, n: 1 },
+    ];
+    assert_eq!(evaluate_parallel(&problem, &train_lines), evaluate(&problem, &train_lines), "Ensure parallel evaluation matches `evaluate`");
+
+    let serial = EvaluationState::new(&problem, &train_lines[..2], Execution::Serial);
+    let parallel = EvaluationState::new(&problem, &train_lines[..2], Execution::Parallel);
+    assert_eq!(serial, parallel, "Ensure parallel evaluation matches serial evaluation");
+    assert_eq!(
+        serial.delta(&problem, &train_lines, Execution::Serial), parallel.delta(&problem, &train_lines, Execution::Parallel),
+        "Ensure parallel incremental evaluation matches serial incremental evaluation"
+    );
+}
+
+/// Ensures scoring neighbours across threads does not change which neighbours are chosen
+#[test]
+fn test_parallel_neighbours() {
+    let problem = gen_random_problem(12, 1.0, 30.0, &mut Rng::with_seed(3));
+    let tabu = |neighbour_threads| {
+        Solver { seed: 7, neighbour_threads, ..test_solver::<TabuSearch>(&problem, TabuParams { initial_timeout: 1000, size_adjust: 10 }) }.solve().solution
+    };
+    assert_eq!(tabu(1), tabu(4), "Ensure parallel tabu search matches serial tabu search");
+
+    let sa = |neighbour_threads| {
+        Solver { seed: 7, neighbour_threads, ..test_solver::<SimAnneal>(&problem, sa_params(540.0, 0.8)) }.solve().solution
+    };
+    assert_eq!(sa(1), sa(3), "Ensure parallel simulated annealing matches serial simulated annealing");
+}
+
+/// Ensures the same seed always gives the same problem and solution
+#[test]
+fn test_seeded() {
+    let problem = gen_random_problem(12, 1.0, 30.0, &mut Rng::with_seed(5));
+    assert_eq!(problem, gen_random_problem(12, 1.0, 30.0, &mut Rng::with_seed(5)), "Ensure generating with a seed is reproducible");
+
+    let solve = |seed| Solver { neighbour_chance: 0.5, seed, ..test_solver::<SimAnneal>(&problem, sa_params(540.0, 0.8)) }.solve().solution;
+    assert_eq!(
+        solution_to_string(&solve(11), &problem, Format::Toml).unwrap(),
+        solution_to_string(&solve(11), &problem, Format::Toml).unwrap(),
+        "Ensure solving with a seed is reproducible"
+    );
+}
+
+/// Ensures the search stops for the right reason when a limit is reached
+#[test]
+fn test_stop_reason() {
+    let problem = gen_random_problem(12, 1.0, 30.0, &mut Rng::with_seed(5));
+    let solver = Solver { max_iterations: 20, seed: 1, ..test_solver::<TabuSearch>(&problem, TabuParams { initial_timeout: 1000, size_adjust: 10 }) };
+    let outcome = solver.solve();
+    assert_eq!((outcome.stop_reason, outcome.iterations), (StopReason::MaxIterations, 20), "Ensure the search runs to the maximum iterations");
+
+    let outcome = Solver { time_limit: Some(Duration::ZERO), ..solver.clone() }.solve();
+    assert_eq!((outcome.stop_reason, outcome.iterations), (StopReason::TimeLimit, 0), "Ensure the time limit stops the search");
+    assert_eq!(outcome.solution.train_lines, big_loop(&problem, ScheduleType::Bidirectional).train_lines, "Ensure the initial solution is returned");
+
+    let outcome = Solver { stall_limit: Some(3), max_iterations: 1000, ..solver.clone() }.solve();
+    assert_eq!(outcome.stop_reason, StopReason::NoImprovement, "Ensure the stall limit stops the search");
+
+    let outcome = Solver { cancel: Some(Arc::new(AtomicBool::new(true))), ..solver }.solve();
+    assert_eq!((outcome.stop_reason, outcome.iterations), (StopReason::Cancelled, 0), "Ensure cancelling stops the search");
+}
+
+/// Ensures observers see every iteration of the search, in order
+#[test]
+fn test_observer() {
+    let problem = gen_random_problem(12, 1.0, 30.0, &mut Rng::with_seed(5));
+    let solver = Solver { max_iterations: 50, seed: 2, ..test_solver::<TabuSearch>(&problem, TabuParams { initial_timeout: 1000, size_adjust: 10 }) };
+    let mut iterations = vec![];
+    let mut best_scores = vec![];
+    let mut finished = None;
+    let outcome = solver.solve_observed(&mut |event: &SearchEvent<'_>| match *event {
+        SearchEvent::Iteration { iteration, best_score, status, .. } => {
+            assert!(matches!(status, MetaheuristicStatus::TabuTenure(_)), "Ensure tabu search reports its tenure");
+            iterations.push(iteration);
+            best_scores.push(best_score);
+        }
+        SearchEvent::Restart { .. } => {}
+        SearchEvent::Finished { stop_reason, iterations, best_score } => finished = Some((stop_reason, iterations, best_score))
+    });
+    assert_eq!(iterations, (1..=50).collect_vec(), "Ensure every iteration is observed in order");
+    assert!(best_scores.windows(2).all(|w| w[1] <= w[0]), "Ensure the best score never gets worse");
+    assert_eq!(
+        finished, Some((StopReason::MaxIterations, 50, outcome.solution.obj_value)),
+        "Ensure the end of the search is observed"
+    );
+}
+
+/// Ensures traces sample the requested iterations, always including the last
+#[test]
+fn test_trace() {
+    let problem = gen_random_problem(12, 1.0, 30.0, &mut Rng::with_seed(5));
+    let solver = Solver { max_iterations: 25, seed: 2, ..test_solver::<SimAnneal>(&problem, sa_params(540.0, 0.8)) };
+    let mut trace = Trace::new(vec![], TraceFormat::Csv, 10).unwrap();
+    solver.solve_observed(&mut trace);
+    let csv = String::from_utf8(trace.finish().unwrap()).unwrap();
+    let iterations = csv.lines().skip(1).map(|row| row.split(',').next().unwrap()).collect_vec();
+    assert_eq!(iterations, ["10", "20", "25"], "Ensure every 10th and the last iteration are traced");
+
+    let mut trace = Trace::new(vec![], TraceFormat::JsonLines, 1).unwrap();
+    solver.solve_observed(&mut trace);
+    let json = String::from_utf8(trace.finish().unwrap()).unwrap();
+    assert_eq!(json.lines().count(), 25, "Ensure every iteration is traced");
+    for row in json.lines() {
+        serde_json::from_str::<serde_json::Value>(row).expect("Ensure each line is valid JSON");
+    }
+}
+
+/// Ensures every move keeps the built tracks and cost of a solution in step with its lines,
+/// and that route and inter-line moves are made
+#[test]
+fn test_move_bookkeeping() {
+    let problem = gen_random_problem(8, 1.0, 30.0, &mut Rng::with_seed(5));
+    let solver = Solver { max_iterations: 300, seed: 4, ..random_walk_solver(&problem) };
+    let mut moves = HashSet::new();
+    let outcome = solver.solve_observed(&mut |event: &SearchEvent<'_>| {
+        if let SearchEvent::Iteration { solution, cost, operator, .. } = *event {
+            let expected = line_tracks(solution).iter().map(|&(a, b)| problem.track_costs[[a, b]]).sum::<f64>()
+                + solution.iter().map(|l| l.n).sum::<usize>() as f64 * problem.train_price;
+            assert!((cost - expected).abs() < 1e-9, "Ensure the cost of {solution:?} is {expected}, not {cost}");
+            moves.extend(operator.map(str::to_string));
+        }
+    });
+    let solution = outcome.solution;
+    assert_eq!(built_tracks(&solution), line_tracks(&solution.train_lines), "Ensure exactly the tracks the lines use are built");
+    assert!(solution.check_feasibility(&problem), "Ensure the solution is within budget");
+    for operator in ["2-opt", "or-opt", "exchange", "relocate", "split", "merge"] {
+        assert!(moves.contains(operator), "Ensure {operator} moves are made, not just {moves:?}");
+    }
+}
+
+/// An operator swapping the first and last stations of each line
+#[derive(Debug)]
+struct SwapEnds;
+impl NeighbourhoodOperator for SwapEnds {
+    fn name(&self) -> &str { "swap-ends" }
+    fn moves(&self, lines: &[TrainLine], _problem: &crate::problem::Problem, _chance: f64, _rng: &mut Rng) -> Vec<Move> {
+        lines.iter().enumerate().map(|(i, line)| {
+            let mut line = line.clone();
+            let last = line.route.len() - 1;
+            line.route.swap(0, last);
+            Move::replace(i, line)
+        }).collect()
+    }
+}
+
+/// An operator which fails the test if it is asked for moves
+#[derive(Debug)]
+struct Unused;
+impl NeighbourhoodOperator for Unused {
+    fn name(&self) -> &str { "unused" }
+    fn moves(&self, _lines: &[TrainLine], _problem: &crate::problem::Problem, _chance: f64, _rng: &mut Rng) -> Vec<Move> {
+        panic!("Ensure the neighbourhood is not generated when the metaheuristic does not use it")
+    }
+}
+
+/// Ensures the solver only uses the operators it is given
+#[test]
+fn test_custom_operators() {
+    let problem = gen_random_problem(8, 1.0, 30.0, &mut Rng::with_seed(5));
+    let solver = Solver {
+        seed: 2,
+        operators: vec![
+            WeightedOperator { operator: Arc::new(SwapEnds), weight: 1.0 },
+            WeightedOperator { operator: Arc::new(AddTrain), weight: 0.5 }
+        ],
+        ..random_walk_solver(&problem)
+    };
+    let mut moves = HashSet::new();
+    solver.solve_observed(&mut |event: &SearchEvent<'_>| {
+        if let SearchEvent::Iteration { operator, .. } = *event {
+            moves.extend(operator.map(str::to_string));
+        }
+    });
+    assert_eq!(moves, HashSet::from(["swap-ends".to_string(), "add-train".to_string()]), "Ensure only the given operators are used");
+}
+
+/// Ensures ALNS improves on where it starts, stays within budget, and keeps its bookkeeping right,
+/// without generating a neighbourhood it does not use, and that it cannot be perturbed for iterated local search
+#[test]
+fn test_alns() {
+    let problem = gen_random_problem(8, 1.0, 30.0, &mut Rng::with_seed(5));
+    let params = AlnsParams {
+        destroy: default_destroy_operators(),
+        repair: default_repair_operators(),
+        destroy_size: 3, reaction: 0.2, segment: 10,
+        initial_temp: 100.0, temp_scale: 0.95
+    };
+    let solver = Solver {
+        max_iterations: 60, seed: 3, verify_delta: true,
+        operators: vec![WeightedOperator { operator: Arc::new(Unused), weight: 1.0 }],
+        ..test_solver::<Alns>(&problem, params)
+    };
+    let initial = big_loop(&problem, ScheduleType::Bidirectional).obj_value;
+    let mut moves = HashSet::new();
+    let outcome = solver.solve_observed(&mut |event: &SearchEvent<'_>| {
+        if let SearchEvent::Iteration { operator, .. } = *event {
+            moves.extend(operator.map(str::to_string));
+        }
+    });
+    let solution = outcome.solution;
+    assert!(solution.obj_value < initial, "Ensure ALNS improves on {initial}, not {}", solution.obj_value);
+    assert!(solution.check_feasibility(&problem), "Ensure the solution is within budget");
+    assert!(solution.train_lines.iter().all(|l| l.route.len() >= 2), "Ensure every line visits at least two stations");
+    assert!(!moves.is_empty() && moves.iter().all(|m| m.contains('+')), "Ensure moves are named by destroy and repair operator, not {moves:?}");
+
+    let cli = Cli::try_parse_from(["train-routing", "solve", "test_problem.toml", "--algorithm", "alns", "--perturbation", "3"]).unwrap();
+    let error = run(cli).expect_err("Ensure iterated local search is rejected, as ALNS has no operators to perturb with");
+    assert!(error.to_string().contains("--perturbation"), "Ensure the error names the option, not {error}");
+    let iterated = MultiStart {
+        local_search: Solver { operators: vec![], ..solver }, starts: 2,
+        iterated: Some(IteratedParams { strength: 3, acceptance: IlsAcceptance::Always })
+    };
+    let result = panic::catch_unwind(AssertUnwindSafe(|| iterated.solve()));
+    assert!(result.is_err(), "Ensure iterated local search without operators to perturb with is rejected");
+}
+
+/// Ensures the genetic and memetic algorithms improve on where they start,
+/// staying within budget and building exactly the tracks their lines use
+#[test]
+fn test_genetic() {
+    let problem = gen_random_problem(8, 1.0, 30.0, &mut Rng::with_seed(5));
+    let initial = big_loop(&problem, ScheduleType::Bidirectional).obj_value;
+    for memetic_iterations in [0, 3] {
+        let solver = GeneticSolver {
+            local_search: Solver {
+                max_iterations: memetic_iterations, neighbour_chance: 0.3, seed: 6,
+                ..test_solver::<TabuSearch>(&problem, TabuParams { initial_timeout: 100, size_adjust: 1 })
+            },
+            generations: 10, stall_generations: None, population_size: 8, elite: 1, tournament_size: 2, mutation_chance: 0.3
+        };
+        let mut best_scores = vec![];
+        let outcome = solver.solve_observed(&mut |event: &SearchEvent<'_>| {
+            if let SearchEvent::Iteration { best_score, status, .. } = *event {
+                assert!(matches!(status, MetaheuristicStatus::Diversity(1..=8)), "Ensure diversity is reported, not {status:?}");
+                best_scores.push(best_score);
+            }
+        });
+        assert_eq!(outcome.iterations, 10, "Ensure every generation is run");
+        assert!(best_scores.windows(2).all(|w| w[1] <= w[0]), "Ensure the best score never gets worse: {best_scores:?}");
+        let solution = outcome.solution;
+        assert!(solution.obj_value < initial, "Ensure {memetic_iterations} memetic iterations improve on {initial}, not {}", solution.obj_value);
+        assert!(solution.check_feasibility(&problem), "Ensure the solution is within budget");
+        assert_eq!(built_tracks(&solution), line_tracks(&solution.train_lines), "Ensure exactly the tracks the lines use are built");
+        assert_eq!(evaluate(&problem, &solution.train_lines), solution.obj_value, "Ensure the objective is the lines' score");
+    }
+}
+
+/// Ensures the genetic algorithm's stall limit counts generations, separately from its local search's stall limit
+#[test]
+fn test_genetic_stall_limit() {
+    let problem = gen_random_problem(8, 1.0, 30.0, &mut Rng::with_seed(5));
+    let solver = |stall_generations, stall_limit| GeneticSolver {
+        local_search: Solver {
+            max_iterations: 3, neighbour_chance: 0.3, seed: 6, stall_limit,
+            ..test_solver::<TabuSearch>(&problem, TabuParams { initial_timeout: 100, size_adjust: 1 })
+        },
+        generations: 60, stall_generations, population_size: 8, elite: 1, tournament_size: 2, mutation_chance: 0.3
+    }.solve();
+    let outcome = solver(Some(3), None);
+    assert_eq!(outcome.stop_reason, StopReason::NoImprovement, "Ensure the stall limit stops the generations");
+    assert!(outcome.iterations < 60, "Ensure the generations stop early, not after {}", outcome.iterations);
+    let outcome = solver(None, Some(1));
+    assert_eq!((outcome.stop_reason, outcome.iterations), (StopReason::MaxIterations, 60), "Ensure the local search's stall limit only stops its searches");
+}
+
+/// Ensures late acceptance hill climbing and great deluge improve on where they start,
+/// and that the great deluge threshold never rises
+#[test]
+fn test_threshold_metaheuristics() {
+    let problem = gen_random_problem(8, 1.0, 30.0, &mut Rng::with_seed(5));
+    let initial = big_loop(&problem, ScheduleType::Bidirectional).obj_value;
+    let lahc = Solver {
+        max_iterations: 40, neighbour_chance: 0.3, seed: 7, ..test_solver::<LateAcceptance>(&problem, LateAcceptanceParams { history: 5 })
+    }.solve();
+    assert!(lahc.solution.obj_value < initial, "Ensure late acceptance improves on {initial}, not {}", lahc.solution.obj_value);
+
+    let deluge = Solver {
+        max_iterations: 40, neighbour_chance: 0.3, seed: 7, ..test_solver::<GreatDeluge>(&problem, GreatDelugeParams { headroom: 0.1 })
+    };
+    let mut thresholds = vec![];
+    let outcome = deluge.solve_observed(&mut |event: &SearchEvent<'_>| {
+        if let SearchEvent::Iteration { status: MetaheuristicStatus::Threshold(threshold), .. } = *event {
+            thresholds.push(threshold);
+        }
+    });
+    assert_eq!(thresholds.len(), 40, "Ensure the threshold is reported every iteration");
+    assert!(thresholds.windows(2).all(|w| w[1] <= w[0]), "Ensure the threshold never rises: {thresholds:?}");
+    assert!(outcome.solution.obj_value < initial, "Ensure great deluge improves on {initial}, not {}", outcome.solution.obj_value);
+
+    // With far more iterations than the time allows, the threshold falls with time instead
+    let timed = Solver { max_iterations: usize::MAX, time_limit: Some(Duration::from_millis(300)), ..deluge };
+    let mut thresholds = vec![];
+    let outcome = timed.solve_observed(&mut |event: &SearchEvent<'_>| {
+        if let SearchEvent::Iteration { status: MetaheuristicStatus::Threshold(threshold), .. } = *event {
+            thresholds.push(threshold);
+        }
+    });
+    let (first, last, best) = (thresholds[0], *thresholds.last().unwrap(), outcome.solution.obj_value);
+    assert_eq!(outcome.stop_reason, StopReason::TimeLimit, "Ensure the search runs until the time limit");
+    assert!(last - best < (first - best) / 2.0, "Ensure the threshold falls from {first} most of the way to {best} by the time limit, not just to {last}");
+}
+
+/// Ensures solver settings are read from config files, rejecting unknown settings
+#[test]
+fn test_solve_config() {
+    let config: SolveConfig = toml::from_str("algorithm = \"lahc\"\nlahc-history = 20\ninitial-temp = 100.0").unwrap();
+    assert_eq!(config, SolveConfig {
+        algorithm: Some(Algorithm::Lahc), lahc_history: Some(20), initial_temp: Some(100.0), ..Default::default()
+    }, "Ensure settings are read by their command-line names");
+    assert!(toml::from_str::<SolveConfig>("lahc_history = 20").is_err(), "Ensure unknown settings are rejected");
+    assert!(toml::from_str::<SolveConfig>("algorithm = \"annealing\"").is_err(), "Ensure unknown algorithms are rejected");
+}
+
+/// Ensures simulated annealing calibrates its temperature to the problem and cools steadily over the whole run,
+/// and that reheating raises the temperature once acceptance collapses, then wears off
+#[test]
+fn test_sa_calibration() {
+    let problem = gen_random_problem(8, 1.0, 30.0, &mut Rng::with_seed(5));
+    let temperatures = |params: SimAnnealParams| {
+        let solver = Solver { neighbour_chance: 0.3, seed: 8, ..test_solver::<SimAnneal>(&problem, params) };
+        let mut temps = vec![];
+        solver.solve_observed(&mut |event: &SearchEvent<'_>| {
+            if let SearchEvent::Iteration { status: MetaheuristicStatus::Temperature(temp), .. } = *event {
+                temps.push(temp);
+            }
+        });
+        temps
+    };
+
+    let calibration = Calibration { initial_acceptance: 0.5, final_acceptance: 1e-5 };
+    let temps = temperatures(SimAnnealParams { initial_temp: 1e9, temp_scale: 1.0, calibration: Some(calibration), reheat: None });
+    // Calibrating ignores the starting temperature given, and moves that leave stations unserved
+    assert!(temps[0] > 0.0 && temps[0] < 1e6, "Ensure the calibrated temperature suits the objective, not {}", temps[0]);
+    assert!(temps.windows(2).all(|w| w[1] < w[0]), "Ensure the temperature cools steadily: {temps:?}");
+    let ratio = temps[0] / temps[temps.len() - 1];
+    assert!(ratio > 10.0, "Ensure the temperature cools to the final acceptance, not by {ratio}");
+
+    // A later start calibrates again, but its schedule is already part way through the time limit on the whole run
+    let multistart = MultiStart {
+        local_search: Solver {
+            max_iterations: 2, neighbour_chance: 0.3, seed: 8, time_limit: Some(Duration::from_secs(2)),
+            ..test_solver::<SimAnneal>(&problem, SimAnnealParams { initial_temp: 1e9, temp_scale: 1.0, calibration: Some(calibration), reheat: None })
+        },
+        starts: 2, iterated: None
+    };
+    let mut temps = vec![];
+    multistart.solve_observed(&mut |event: &SearchEvent<'_>| {
+        if let SearchEvent::Iteration { iteration, status: MetaheuristicStatus::Temperature(temp), .. } = *event {
+            temps.push(temp);
+            if iteration <= 2 {
+                thread::sleep(Duration::from_millis(700));
+            }
+        }
+    });
+    // Every calibrated schedule cools by the same factor overall, so how far the second start cools
+    // over its last iteration shows how much of its schedule was left
+    let overall = calibration.initial_acceptance.ln() / calibration.final_acceptance.ln();
+    let left = (temps[3] / temps[2]).ln() / overall.ln();
+    assert!(left < 0.45, "Ensure the second start's schedule is already part way through, not with {left} of it left");
+
+    let reheat = Reheat { window: 5, min_acceptance: 0.5, factor: 10.0 };
+    let temps = temperatures(SimAnnealParams { initial_temp: 1e-9, temp_scale: 1.0, calibration: None, reheat: Some(reheat) });
+    assert!(temps.windows(2).any(|w| w[1] > w[0]), "Ensure the search reheats when acceptance collapses: {temps:?}");
+    assert!(temps.windows(2).any(|w| w[1] < w[0]), "Ensure reheating wears off: {temps:?}");
+}
+
+/// Ensures solutions have the same canonical form and hash whatever order their lines are in,
+/// whichever way bidirectional routes are read, and wherever circular routes start
+#[test]
+fn test_solution_hash() {
+    let line = |route: &[usize], ty| TrainLine { route: route.to_vec(), ty, n: 1 };
+    let lines = vec![line(&[0, 1, 2], ScheduleType::Bidirectional), line(&[3, 4, 5], ScheduleType::Circular)];
+    let equivalent = vec![line(&[3, 4, 5], ScheduleType::Circular), line(&[2, 1, 0], ScheduleType::Bidirectional)];
+    assert_eq!(solution_hash(&lines), solution_hash(&equivalent), "Ensure line order and bidirectional direction are ignored");
+    let rotated = vec![line(&[4, 5, 3], ScheduleType::Circular), line(&[0, 1, 2], ScheduleType::Bidirectional)];
+    assert_eq!(solution_hash(&lines), solution_hash(&rotated), "Ensure the starting station of circular lines is ignored");
+    assert_eq!(canonical_lines(&lines), canonical_lines(&equivalent), "Ensure equivalent lines have the same canonical form");
+    assert_eq!(canonical_lines(&lines), canonical_lines(&rotated), "Ensure equivalent lines have the same canonical form");
+    let reversed_circle = vec![line(&[0, 1, 2], ScheduleType::Bidirectional), line(&[5, 4, 3], ScheduleType::Circular)];
+    assert_ne!(solution_hash(&lines), solution_hash(&reversed_circle), "Ensure circular lines keep their direction");
+    assert_ne!(canonical_lines(&lines), canonical_lines(&reversed_circle), "Ensure circular lines keep their direction");
+    // The least rotation is found even when the least station appears more than once
+    assert_eq!(line(&[1, 0, 2, 0, 1], ScheduleType::Circular).canonical().route, vec![0, 1, 1, 0, 2]);
+}
+
+/// Ensures lines are renumbered as removing lines moves the last line into their place
+#[test]
+fn test_changes_renumber() {
+    // Removing line 3 then line 1 from four lines leaves lines 0 and 2, with line 2 moved into place 1
+    let changes = Changes { removed_lines: vec![3, 1], lines_before: 4, ..Default::default() };
+    let renumbered = (0..4).map(|line| changes.renumber(line)).collect_vec();
+    assert_eq!(renumbered, vec![Some(0), None, Some(1), None], "Ensure removed lines are dropped and moved lines renumbered");
+}
+
+/// Ensures multiple starts and iterated local search keep the best of their starts,
+/// numbering iterations across every start, and report why the last start stopped
+#[test]
+fn test_multistart() {
+    let problem = gen_random_problem(8, 1.0, 30.0, &mut Rng::with_seed(5));
+    let local_search = Solver { max_iterations: 8, neighbour_chance: 0.3, seed: 9, ..test_solver::<SimAnneal>(&problem, sa_params(10.0, 0.9)) };
+    let iterated = IteratedParams { strength: 3, acceptance: IlsAcceptance::Better };
+    for iterated in [None, Some(iterated)] {
+        let multistart = MultiStart { local_search: local_search.clone(), starts: 5, iterated };
+        let mut iterations = vec![];
+        let result = multistart.solve_observed(&mut |event: &SearchEvent<'_>| {
+            if let SearchEvent::Iteration { iteration, .. } = *event {
+                iterations.push(iteration);
+            }
+        });
+        assert_eq!(result.scores.len(), 5, "Ensure every start is run");
+        let best = result.scores.iter().copied().fold(f64::INFINITY, f64::min);
+        assert_eq!(result.outcome.solution.obj_value, best, "Ensure the best start's solution is kept");
+        assert_eq!(evaluate(&problem, &result.outcome.solution.train_lines), best, "Ensure the objective is the lines' score");
+        assert!(result.outcome.solution.check_feasibility(&problem), "Ensure the solution is within budget");
+        assert_eq!(iterations, (1..=result.outcome.iterations).collect_vec(), "Ensure iterations are numbered across starts");
+        assert_eq!(ScoreSummary::new(&result.scores).unwrap().best, best, "Ensure the summary's best score is the best start's");
+    }
+
+    let stalling = MultiStart { local_search: Solver { max_iterations: 50, stall_limit: Some(1), ..local_search }, starts: 3, iterated: None };
+    let result = stalling.solve();
+    assert_eq!(result.scores.len(), 3, "Ensure a start which stops improving only stops itself");
+    assert_eq!(result.outcome.stop_reason, StopReason::NoImprovement, "Ensure why the last start stopped is reported");
+}
+
+/// Ensures a portfolio returns the best solution of any island, that sharing solutions
+/// between islands does not depend on how their threads are scheduled, and that islands carry on where they stopped
+#[test]
+fn test_portfolio() {
+    let problem = gen_random_problem(8, 1.0, 30.0, &mut Rng::with_seed(5));
+    let solver = |seed| Solver { max_iterations: 10, neighbour_chance: 0.3, seed, ..test_solver::<SimAnneal>(&problem, sa_params(10.0, 0.9)) };
+    let run = || {
+        let islands: Vec<(String, Box<dyn Island>)> = vec![
+            ("sa".to_string(), Box::new(solver(1))),
+            ("sa".to_string(), Box::new(solver(2))),
+            ("lahc".to_string(), Box::new(Solver {
+                max_iterations: 10, neighbour_chance: 0.3, seed: 3, ..test_solver::<LateAcceptance>(&problem, LateAcceptanceParams { history: 5 })
+            }))
+        ];
+        let mut migrations = vec![];
+        let result = Portfolio { islands, migration_interval: 3 }.solve_observed(&mut |event: &SearchEvent<'_>| {
+            if let SearchEvent::Iteration { iteration, .. } = *event {
+                migrations.push(iteration);
+            }
+        });
+        (result, migrations)
+    };
+    let (result, migrations) = run();
+    assert_eq!(result.islands.iter().map(|s| s.name.as_str()).collect_vec(), ["sa", "sa", "lahc"], "Ensure every island is reported, in order");
+    assert!(result.islands.iter().all(|s| s.iterations == 10), "Ensure every island runs all its iterations");
+    let best = result.islands.iter().map(|s| s.best_score).fold(f64::INFINITY, f64::min);
+    assert_eq!(result.outcome.solution.obj_value, best, "Ensure the best island's solution is kept");
+    assert_eq!(evaluate(&problem, &result.outcome.solution.train_lines), best, "Ensure the objective is the lines' score");
+    assert!(result.outcome.solution.check_feasibility(&problem), "Ensure the solution is within budget");
+    assert_eq!(migrations, (1..=result.outcome.iterations).collect_vec(), "Ensure the observer hears about every migration");
+    assert_eq!(run().0, result, "Ensure the same seeds give the same result");
+
+    let islands: Vec<(String, Box<dyn Island>)> = vec![("sa".to_string(), Box::new(Solver { max_iterations: 1000, stall_limit: Some(5), ..solver(1) }))];
+    let result = Portfolio { islands, migration_interval: 3 }.solve();
+    assert_eq!(result.outcome.stop_reason, StopReason::NoImprovement, "Ensure the stall limit counts iterations across migrations");
+}
+
+/// Ensures a solution found with overridden evaluation settings can be evaluated, validated
+/// and warm started from, with its objective checked against the settings it was found with
+#[test]
+fn test_evaluation_override_round_trip() {
+    let cli = |args: &[&str]| run(Cli::try_parse_from(["train-routing"].iter().chain(args)).unwrap());
+    let solve = ["solve", "medium_random_problem.toml", "--seed", "1", "--max-iterations", "30", "--transfer-penalty", "0.01", "-o", "__test_override.toml"];
+    cli(&solve).expect("Ensure solving with an override succeeds");
+    cli(&["evaluate", "medium_random_problem.toml", "__test_override.toml", "--transfer-penalty", "0.01"]).expect("Ensure the solution evaluates with the same override");
+    cli(&["evaluate", "medium_random_problem.toml", "__test_override.toml"]).expect("Ensure the solution evaluates without the override");
+    cli(&["validate", "medium_random_problem.toml", "-s", "__test_override.toml"]).expect("Ensure the solution validates");
+    cli(&["solve", "medium_random_problem.toml", "--initial", "__test_override.toml", "--max-iterations", "5", "-o", "__test_warm.toml"])
+        .expect("Ensure the solution can be warm started from");
+
+    let contents = fs::read_to_string("__test_override.toml").unwrap().replace("transfer_penalty = 0.01", "transfer_penalty = 0.02");
+    let problem = parse_problem("medium_random_problem.toml").unwrap();
+    assert!(matches!(
+        solution_from_str(&contents, &problem, Format::Toml),
+        Err(SolutionError::ObjectiveMismatch { .. })
+    ), "Ensure the objective is checked against the stored evaluation model");
+    fs::remove_file("__test_override.toml").unwrap();
+    fs::remove_file("__test_warm.toml").unwrap();
+}
+
+/// Ensures the command-line interface can solve more than once in the same process
+#[test]
+fn test_repeated_solve() {
+    for _ in 0..2 {
+        let cli = Cli::try_parse_from(["train-routing", "solve", "test_problem.toml", "--seed", "1", "--max-iterations", "5", "-o", "__test_repeated.toml"]).unwrap();
+        run(cli).expect("Ensure every solve succeeds");
+    }
+    fs::remove_file("__test_repeated.toml").unwrap();
+}
+
+/// An island which panics as soon as it starts
+struct PanickingIsland;
+impl Island for PanickingIsland {
+    fn run(&self, _index: usize, _migration: &Migration<'_>) -> IslandStats {
+        panic!("island failed");
+    }
+}
+
+/// Ensures an island panicking does not leave the others waiting for it, and that its panic is passed on
+#[test]
+fn test_portfolio_panic() {
+    let problem = gen_random_problem(8, 1.0, 30.0, &mut Rng::with_seed(5));
+    let params = sa_params(10.0, 0.9);
+    let islands: Vec<(String, Box<dyn Island>)> = vec![
+        ("sa".to_string(), Box::new(Solver { max_iterations: 10, ..test_solver::<SimAnneal>(&problem, params) })),
+        ("panic".to_string(), Box::new(PanickingIsland)),
+        ("sa".to_string(), Box::new(Solver { max_iterations: 10, seed: 1, ..test_solver::<SimAnneal>(&problem, params) }))
+    ];
+    let portfolio = Portfolio { islands, migration_interval: 3 };
+    let payload = panic::catch_unwind(AssertUnwindSafe(|| portfolio.solve())).expect_err("Ensure the island's panic is passed on");
+    assert_eq!(payload.downcast_ref::<&str>(), Some(&"island failed"), "Ensure the island's own panic is passed on");
+}
+
+/// Ensures a search can start from an existing solution, never returning a worse one,
+/// and that solutions which cannot be used for the problem are rejected
+#[test]
+fn test_solve_from() {
+    let problem = gen_random_problem(8, 1.0, 30.0, &mut Rng::with_seed(5));
+    let solver = Solver {
+        max_iterations: 10, neighbour_chance: 0.3, seed: 4, ..test_solver::<TabuSearch>(&problem, TabuParams { initial_timeout: 10, size_adjust: 1 })
+    };
+    let initial = solver.solve().solution;
+    let outcome = solver.solve_from(&initial).unwrap();
+    assert!(outcome.solution.obj_value <= initial.obj_value, "Ensure the search never returns a worse solution than it started from");
+    assert!(outcome.solution.check_feasibility(&problem), "Ensure the solution is within budget");
+    assert_eq!(solver.solve_from(&initial).unwrap(), outcome, "Ensure the same seed gives the same result");
+
+    let invalid = |change: &dyn Fn(&mut Solution)| {
+        let mut solution = initial.clone();
+        change(&mut solution);
+        solver.solve_from(&solution).unwrap_err()
+    };
+    assert_eq!(invalid(&|s| s.train_lines.clear()), InvalidSolution::NoLines);
+    assert_eq!(invalid(&|s| s.train_lines[0].route[0] = 8), InvalidSolution::StationOutOfRange { line: 0, station: 8, n: 8 });
+    assert_eq!(invalid(&|s| s.train_lines[0].route.truncate(1)), InvalidSolution::ShortLine { line: 0 });
+    let first = initial.train_lines[0].route[0];
+    assert_eq!(invalid(&|s| s.train_lines[0].route.push(first)), InvalidSolution::RepeatedStation { line: 0, station: first });
+    assert_eq!(invalid(&|s| s.train_lines[0].n = 0), InvalidSolution::NoTrains { line: 0 });
+    assert!(matches!(invalid(&|s| s.built_tracks.fill(false)), InvalidSolution::TrackNotBuilt { line: 0, .. }));
+    assert!(matches!(invalid(&|s| s.train_lines[0].n = 1000), InvalidSolution::OverBudget { .. }));
+}